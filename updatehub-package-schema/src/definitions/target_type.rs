@@ -12,6 +12,16 @@ pub enum TargetType {
     Device(PathBuf),
     UBIVolume(String),
     MTDName(String),
+    /// Partition found by its GPT partition label
+    PartLabel(String),
+    /// Partition found by its GPT partition UUID, or MBR disk signature
+    /// and partition number
+    PartUUID(String),
+    /// Partition found by the UUID of the filesystem it holds
+    #[serde(rename = "fs-uuid")]
+    FsUUID(String),
+    /// Directory, already mounted, where the content is written to
+    Path(PathBuf),
 }
 
 #[cfg(test)]
@@ -38,5 +48,29 @@ mod test {
             }))
             .unwrap()
         );
+        assert_eq!(
+            TargetType::PartLabel("rootfs-a".to_string()),
+            serde_json::from_value::<TargetType>(json!({
+                "target-type": "partlabel",
+                "target": "rootfs-a",
+            }))
+            .unwrap()
+        );
+        assert_eq!(
+            TargetType::FsUUID("6a8c3e6d-1b2f-4c9e-9d8a-0f1e2d3c4b5a".to_string()),
+            serde_json::from_value::<TargetType>(json!({
+                "target-type": "fs-uuid",
+                "target": "6a8c3e6d-1b2f-4c9e-9d8a-0f1e2d3c4b5a",
+            }))
+            .unwrap()
+        );
+        assert_eq!(
+            TargetType::Path(PathBuf::from("/var/lib/app")),
+            serde_json::from_value::<TargetType>(json!({
+                "target-type": "path",
+                "target": "/var/lib/app",
+            }))
+            .unwrap()
+        );
     }
 }
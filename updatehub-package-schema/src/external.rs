@@ -0,0 +1,64 @@
+// Copyright (C) 2019 O.S. Systems Sofware LTDA
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+#[derive(Deserialize, Serialize, PartialEq, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub struct External {
+    pub mode: String,
+    pub filename: String,
+    pub size: u64,
+    pub sha256sum: String,
+
+    /// Remaining, mode specific, properties of the object
+    #[serde(flatten)]
+    pub properties: Map<String, Value>,
+}
+
+#[test]
+fn deserialize() {
+    use crate::Object;
+    use pretty_assertions::assert_eq;
+    use serde_json::json;
+
+    let mut properties = Map::new();
+    properties.insert("target".to_string(), json!("/dev/mmcblk0p3"));
+    properties.insert("slots".to_string(), json!([1, 2]));
+
+    assert_eq!(
+        Object::External(Box::new(External {
+            mode: "vendor-fpga".to_string(),
+            filename: "bitstream.bin".to_string(),
+            size: 1024,
+            sha256sum: "cfe2be1c64b0387500853de0f48303e3de7b1c6f1508dc719eeafa0d41c36722"
+                .to_string(),
+            properties,
+        })),
+        serde_json::from_value::<Object>(json!({
+            "mode": "vendor-fpga",
+            "filename": "bitstream.bin",
+            "size": 1024,
+            "sha256sum": "cfe2be1c64b0387500853de0f48303e3de7b1c6f1508dc719eeafa0d41c36722",
+            "target": "/dev/mmcblk0p3",
+            "slots": [1, 2],
+        }))
+        .unwrap()
+    );
+}
+
+#[test]
+fn known_mode_errors_are_kept() {
+    use crate::Object;
+    use serde_json::json;
+
+    assert!(serde_json::from_value::<Object>(json!({
+        "mode": "shell",
+        "filename": "script.sh",
+        "size": "invalid",
+        "sha256sum": "cfe2be1c64b0387500853de0f48303e3de7b1c6f1508dc719eeafa0d41c36722",
+    }))
+    .is_err());
+}
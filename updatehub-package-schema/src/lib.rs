@@ -3,10 +3,13 @@
 // SPDX-License-Identifier: Apache-2.0
 
 mod copy;
+mod external;
 mod flash;
 mod imxkobs;
 mod mender;
 mod raw;
+mod raw_delta;
+mod shell;
 mod tarball;
 mod test;
 mod ubifs;
@@ -17,24 +20,74 @@ pub mod definitions;
 /// Objects representing each possible install mode
 pub mod objects {
     pub use crate::{
-        copy::Copy, flash::Flash, imxkobs::Imxkobs, raw::Raw, tarball::Tarball, test::Test,
-        ubifs::Ubifs,
+        copy::Copy, external::External, flash::Flash, imxkobs::Imxkobs, mender::Mender, raw::Raw,
+        raw_delta::RawDelta, shell::Shell, tarball::Tarball, test::Test, ubifs::Ubifs,
+        zephyr::Zephyr,
     };
 }
 
-use serde::Deserialize;
+use serde::{de, Deserialize, Deserializer};
 
 /// Represents the install mode for the object data
-#[derive(Deserialize, PartialEq, Debug)]
+#[derive(PartialEq, Debug)]
+pub enum Object {
+    Copy(Box<objects::Copy>),
+    Flash(Box<objects::Flash>),
+    Imxkobs(Box<objects::Imxkobs>),
+    Mender(Box<objects::Mender>),
+    Raw(Box<objects::Raw>),
+    RawDelta(Box<objects::RawDelta>),
+    Shell(Box<objects::Shell>),
+    Tarball(Box<objects::Tarball>),
+    Test(Box<objects::Test>),
+    Ubifs(Box<objects::Ubifs>),
+    Zephyr(Box<objects::Zephyr>),
+    /// Object of a mode unknown to the agent, installed by an external
+    /// handler
+    External(Box<objects::External>),
+}
+
+// Objects of the modes known to the agent. Any other mode is parsed
+// apart as an `External` object.
+#[derive(Deserialize)]
 #[serde(tag = "mode")]
 #[serde(rename_all = "lowercase")]
-pub enum Object {
+enum Known {
     Copy(Box<objects::Copy>),
     Flash(Box<objects::Flash>),
     Imxkobs(Box<objects::Imxkobs>),
+    Mender(Box<objects::Mender>),
     Raw(Box<objects::Raw>),
+    #[serde(rename = "raw-delta")]
+    RawDelta(Box<objects::RawDelta>),
+    Shell(Box<objects::Shell>),
     Tarball(Box<objects::Tarball>),
     Test(Box<objects::Test>),
     Ubifs(Box<objects::Ubifs>),
-    // FIXME: Add support for the missing modes: Mende Zephyr
+    Zephyr(Box<objects::Zephyr>),
+    #[serde(other)]
+    Unknown,
+}
+
+impl<'de> Deserialize<'de> for Object {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = serde_json::Value::deserialize(deserializer)?;
+
+        Ok(match Known::deserialize(&value).map_err(de::Error::custom)? {
+            Known::Copy(o) => Object::Copy(o),
+            Known::Flash(o) => Object::Flash(o),
+            Known::Imxkobs(o) => Object::Imxkobs(o),
+            Known::Mender(o) => Object::Mender(o),
+            Known::Raw(o) => Object::Raw(o),
+            Known::RawDelta(o) => Object::RawDelta(o),
+            Known::Shell(o) => Object::Shell(o),
+            Known::Tarball(o) => Object::Tarball(o),
+            Known::Test(o) => Object::Test(o),
+            Known::Ubifs(o) => Object::Ubifs(o),
+            Known::Zephyr(o) => Object::Zephyr(o),
+            Known::Unknown => Object::External(
+                objects::External::deserialize(value).map_err(de::Error::custom)?.into(),
+            ),
+        })
+    }
 }
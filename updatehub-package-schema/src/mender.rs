@@ -2,13 +2,17 @@
 //
 // SPDX-License-Identifier: Apache-2.0
 
+use crate::definitions::TargetType;
 use serde::Deserialize;
 
 #[derive(Deserialize, PartialEq, Debug)]
+#[serde(rename_all = "kebab-case")]
 pub struct Mender {
     pub filename: String,
     pub size: u64,
     pub sha256sum: String,
+    #[serde(flatten)]
+    pub target: TargetType,
 }
 
 #[test]
@@ -22,11 +26,14 @@ fn deserialize() {
             size: 1024,
             sha256sum: "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
                 .to_string(),
+            target: TargetType::Device(std::path::PathBuf::from("/dev/mmcblk0p2")),
         },
         serde_json::from_value::<Mender>(json!({
             "filename": "artifact.mender",
             "size": 1024,
             "sha256sum": "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855",
+            "target-type": "device",
+            "target": "/dev/mmcblk0p2",
         }))
         .unwrap()
     );
@@ -29,6 +29,9 @@ pub struct Raw {
     pub count: Count,
     #[serde(default)]
     pub truncate: Truncate,
+    pub chunk_index: Option<String>,
+    #[serde(default)]
+    pub write_only_changed: bool,
 }
 
 #[test]
@@ -53,6 +56,10 @@ fn deserialize() {
             seek: u64::default(),
             count: Count::default(),
             truncate: Truncate::default(),
+            chunk_index: Some(
+                "d7a8fbb307d7809469ca9abcb0082e4f8d5651e46d3cdb762d02d0bf37c9e592".to_string()
+            ),
+            write_only_changed: true,
         },
         serde_json::from_value::<Raw>(json!({
             "filename": "etc/passwd",
@@ -62,7 +69,9 @@ fn deserialize() {
             "target-type": "device",
             "target": "/dev/sdb",
             "compressed": true,
-            "required-uncompressed-size": 2048
+            "required-uncompressed-size": 2048,
+            "chunk-index": "d7a8fbb307d7809469ca9abcb0082e4f8d5651e46d3cdb762d02d0bf37c9e592",
+            "write-only-changed": true
         }))
         .unwrap()
     );
@@ -0,0 +1,60 @@
+// Copyright (C) 2019 O.S. Systems Sofware LTDA
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::definitions::TargetType;
+use serde::Deserialize;
+use std::path::PathBuf;
+
+#[derive(Deserialize, PartialEq, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub struct RawDelta {
+    pub filename: String,
+    pub size: u64,
+    pub sha256sum: String,
+    #[serde(flatten)]
+    pub target_type: TargetType,
+
+    pub source: PathBuf,
+    pub source_size: u64,
+    pub source_sha256sum: String,
+    pub target_size: u64,
+    pub target_sha256sum: String,
+}
+
+#[test]
+fn deserialize() {
+    use pretty_assertions::assert_eq;
+    use serde_json::json;
+
+    assert_eq!(
+        RawDelta {
+            filename: "rootfs.delta".to_string(),
+            size: 1024,
+            sha256sum: "cfe2be1c64b0387500853de0f48303e3de7b1c6f1508dc719eeafa0d41c36722"
+                .to_string(),
+            target_type: TargetType::Device(PathBuf::from("/dev/sdb2")),
+
+            source: PathBuf::from("/dev/sdb1"),
+            source_size: 4096,
+            source_sha256sum: "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+                .to_string(),
+            target_size: 8192,
+            target_sha256sum: "d7a8fbb307d7809469ca9abcb0082e4f8d5651e46d3cdb762d02d0bf37c9e592"
+                .to_string(),
+        },
+        serde_json::from_value::<RawDelta>(json!({
+            "filename": "rootfs.delta",
+            "size": 1024,
+            "sha256sum": "cfe2be1c64b0387500853de0f48303e3de7b1c6f1508dc719eeafa0d41c36722",
+            "target-type": "device",
+            "target": "/dev/sdb2",
+            "source": "/dev/sdb1",
+            "source-size": 4096,
+            "source-sha256sum": "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855",
+            "target-size": 8192,
+            "target-sha256sum": "d7a8fbb307d7809469ca9abcb0082e4f8d5651e46d3cdb762d02d0bf37c9e592",
+        }))
+        .unwrap()
+    );
+}
@@ -2,8 +2,13 @@
 //
 // SPDX-License-Identifier: Apache-2.0
 
-use crate::{firmware::Metadata, runtime_settings::RuntimeSettings, update_package::UpdatePackage};
+use crate::{
+    firmware::Metadata,
+    runtime_settings::RuntimeSettings,
+    update_package::UpdatePackage,
+};
 
+use crypto_hash::{hex_digest, Algorithm};
 use failure::bail;
 use reqwest::{
     header::{HeaderMap, HeaderName, CONTENT_TYPE, RANGE, USER_AGENT},
@@ -23,7 +28,9 @@ pub(crate) struct Api<'a> {
 #[derive(Debug)]
 pub(crate) enum ProbeResponse {
     NoUpdate,
-    Update(UpdatePackage),
+    /// Update package along with its base64 encoded signature, which is
+    /// only decoded when signatures are checked.
+    Update(UpdatePackage, Option<String>),
     ExtraPoll(i64),
 }
 
@@ -67,7 +74,15 @@ impl<'a> Api<'a> {
                     .and_then(|extra_poll| extra_poll.parse().ok())
                 {
                     Some(extra_poll) => Ok(ProbeResponse::ExtraPoll(extra_poll)),
-                    None => Ok(ProbeResponse::Update(UpdatePackage::parse(&response.text()?)?)),
+                    None => {
+                        let signature = response
+                            .headers()
+                            .get("signature")
+                            .map(|s| String::from_utf8_lossy(s.as_bytes()).into_owned());
+                        let update_package = UpdatePackage::parse(&response.text()?)?;
+
+                        Ok(ProbeResponse::Update(update_package, signature))
+                    }
                 }
             }
             _ => bail!("Invalid response. Status: {}", response.status()),
@@ -110,6 +125,37 @@ impl<'a> Api<'a> {
         bail!("Couldn't download the object {}", object)
     }
 
+    pub fn download_chunk(
+        &self,
+        product_uid: &str,
+        package_uid: &str,
+        chunk_store: &Path,
+        chunk: &str,
+    ) -> Result<(), failure::Error> {
+        let mut response = self
+            .client()?
+            .get(&format!(
+                "{}/products/{}/packages/{}/chunks/{}",
+                &self.server, product_uid, package_uid, chunk
+            ))
+            .send()?;
+
+        if response.status().is_success() {
+            // Chunks found on the store are taken as valid, so it is only
+            // moved there once fully downloaded and checked.
+            let partial = chunk_store.join(format!("{}.partial", chunk));
+            response.copy_to(&mut std::fs::File::create(&partial)?)?;
+            if hex_digest(Algorithm::SHA256, &std::fs::read(&partial)?) != chunk {
+                std::fs::remove_file(&partial)?;
+                bail!("Chunk {} is corrupted", chunk);
+            }
+            std::fs::rename(&partial, chunk_store.join(chunk))?;
+            return Ok(());
+        }
+
+        bail!("Couldn't download the chunk {}", chunk)
+    }
+
     pub fn report(
         &self,
         state: &str,
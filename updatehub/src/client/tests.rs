@@ -15,6 +15,7 @@ use serde_json::json;
 pub(crate) enum FakeServer {
     NoUpdate,
     HasUpdate,
+    HasSignedUpdate(String),
     ExtraPoll,
     ErrorOnce,
     InvalidHardware,
@@ -58,6 +59,14 @@ pub(crate) fn create_mock_server(server: FakeServer) -> Mock {
             .with_status(200)
             .with_body(&get_update_json(SHA256SUM).to_string())
             .create(),
+        FakeServer::HasSignedUpdate(signature) => mock("POST", "/upgrades")
+            .match_header("Content-Type", "application/json")
+            .match_header("Api-Content-Type", "application/vnd.updatehub-v1+json")
+            .match_body(fake_device_reply_body(2, "board"))
+            .with_status(200)
+            .with_header("Signature", &signature)
+            .with_body(get_update_json(SHA256SUM).to_string())
+            .create(),
         FakeServer::ExtraPoll => mock("POST", "/upgrades")
             .match_header("Content-Type", "application/json")
             .match_header("Api-Content-Type", "application/vnd.updatehub-v1+json")
@@ -140,6 +149,32 @@ fn probe_requirements() {
     mock.assert();
 }
 
+#[test]
+fn probe_signed_update() {
+    use crate::update_package::{
+        signature::tests::sign,
+        tests::{get_update_json, SHA256SUM},
+    };
+    use openssl::pkey::PKey;
+    use pretty_assertions::assert_eq;
+
+    let signature =
+        sign(&PKey::generate_ed25519().unwrap(), &get_update_json(SHA256SUM).to_string());
+    let mock = create_mock_server(FakeServer::HasSignedUpdate(signature.clone()));
+    let probe = Api::new(&Settings::default().network.server_address)
+        .probe(
+            &RuntimeSettings::default(),
+            &Metadata::from_path(&create_fake_metadata(FakeDevice::HasUpdate)).unwrap(),
+        )
+        .unwrap();
+    mock.assert();
+
+    match probe {
+        ProbeResponse::Update(_, s) => assert_eq!(s, Some(signature)),
+        p => panic!("Unexpected probe response: {:?}", p),
+    }
+}
+
 #[test]
 fn download_object() {
     let metadata = Metadata::from_path(&create_fake_metadata(FakeDevice::NoUpdate)).unwrap();
@@ -224,6 +259,46 @@ fn download_object() {
     tempdir.close().expect("Fail to cleanup the tempdir");
 }
 
+#[test]
+fn download_chunk() {
+    use pretty_assertions::assert_eq;
+    use tempfile::tempdir;
+
+    let metadata = Metadata::from_path(&create_fake_metadata(FakeDevice::NoUpdate)).unwrap();
+    let chunk = hex_digest(Algorithm::SHA256, b"chunk content");
+    let chunk_mock = |chunk: &str| {
+        mock(
+            "GET",
+            format!("/products/{}/packages/{}/chunks/{}", metadata.product_uid, "package_id", chunk)
+                .as_str(),
+        )
+        .match_header("Api-Content-Type", "application/vnd.updatehub-v1+json")
+        .with_status(200)
+        .with_body("chunk content")
+        .create()
+    };
+
+    let chunk_store = tempdir().unwrap();
+    let mock = chunk_mock(&chunk);
+    Api::new(&Settings::default().network.server_address)
+        .download_chunk(&metadata.product_uid, "package_id", chunk_store.path(), &chunk)
+        .expect("Failed to download the chunk.");
+
+    mock.assert();
+    assert_eq!(std::fs::read(chunk_store.path().join(&chunk)).unwrap(), b"chunk content");
+
+    // Content not matching the chunk name is not stored
+    let mock = chunk_mock("corrupted");
+    assert!(
+        Api::new(&Settings::default().network.server_address)
+            .download_chunk(&metadata.product_uid, "package_id", chunk_store.path(), "corrupted")
+            .is_err()
+    );
+
+    mock.assert();
+    assert_eq!(std::fs::read_dir(chunk_store.path()).unwrap().count(), 1);
+}
+
 #[test]
 fn report_success() {
     use crate::firmware::tests::{create_fake_metadata, FakeDevice};
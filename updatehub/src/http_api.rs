@@ -16,7 +16,8 @@ impl API {
             .route("/info", web::get().to(API::info))
             .route("/log", web::get().to(API::log))
             .route("/probe", web::post().to(API::probe))
-            .route("/update/download/abort", web::post().to(API::download_abort));
+            .route("/update/download/abort", web::post().to(API::download_abort))
+            .route("/update/dry-run", web::get().to(API::dry_run));
     }
 
     fn info(agent: web::Data<API>) -> impl Responder {
@@ -34,6 +35,10 @@ impl API {
     fn download_abort(agent: web::Data<API>) -> impl Responder {
         agent.0.send(actor::download_abort::Request).wait()
     }
+
+    fn dry_run(agent: web::Data<API>) -> impl Responder {
+        agent.0.send(actor::dry_run::Request).wait()
+    }
 }
 
 impl Responder for actor::download_abort::Response {
@@ -54,6 +59,20 @@ impl Responder for actor::download_abort::Response {
     }
 }
 
+impl Responder for actor::dry_run::Response {
+    type Error = Error;
+    type Future = HttpResponse;
+
+    fn respond_to(self, _: &HttpRequest) -> Self::Future {
+        match self {
+            actor::dry_run::Response::Plan(dry_run) => HttpResponse::Ok().json(dry_run),
+            actor::dry_run::Response::NotFound => HttpResponse::NotFound().json(json!({
+                "error": "there is no dry-run installation to be reported"
+            })),
+        }
+    }
+}
+
 impl Responder for actor::probe::Response {
     type Error = Error;
     type Future = HttpResponse;
@@ -0,0 +1,260 @@
+// Copyright (C) 2019 O.S. Systems Sofware LTDA
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::{client::Api, utils::definitions::TargetTypeExt};
+use crypto_hash::{hex_digest, Algorithm};
+use failure::bail;
+use pkg_schema::Object;
+use serde::Deserialize;
+use slog_scope::{error, info};
+use std::{
+    collections::HashSet,
+    fs,
+    io::{BufReader, BufWriter, Read, Write},
+    path::{Path, PathBuf},
+};
+
+/// Directory, inside the download directory, holding the chunks while
+/// the object is assembled. It is kept if the download is interrupted
+/// so the chunks are reused when it is resumed.
+const CHUNK_STORE: &str = "chunks";
+
+/// Index listing, in order, the fixed size chunks an object is made of.
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct Index {
+    chunk_size: u64,
+    chunks: Vec<String>,
+}
+
+/// Object downloaded by its chunks, reusing the ones already found on
+/// the active installation set.
+#[derive(Debug)]
+pub(crate) struct Chunked {
+    index: String,
+    seed: Option<PathBuf>,
+}
+
+impl Chunked {
+    /// Gets the chunked download of `object` when it has a chunk index.
+    /// `active` is the same object on the active installation set, which
+    /// target is used to seed the chunk store.
+    pub(crate) fn from_object(object: &Object, active: Option<&Object>) -> Option<Self> {
+        let index = match object {
+            Object::Raw(o) => o.chunk_index.clone()?,
+            _ => return None,
+        };
+        let seed = match active {
+            Some(Object::Raw(o)) if o.target_type.is_block_device() => {
+                o.target_type.get_target().ok()
+            }
+            _ => None,
+        };
+
+        Some(Chunked { index, seed })
+    }
+
+    /// Name of the chunk index, which is kept in the download directory.
+    pub(crate) fn index(&self) -> &str {
+        &self.index
+    }
+
+    pub(crate) fn download(
+        &self,
+        api: &Api,
+        product_uid: &str,
+        package_uid: &str,
+        download_dir: &Path,
+        object: &str,
+    ) -> Result<(), failure::Error> {
+        let index_path = download_dir.join(&self.index);
+        if sha256sum(&index_path).ok().as_ref() != Some(&self.index) {
+            api.download_object(product_uid, package_uid, download_dir, &self.index)?;
+            if sha256sum(&index_path)? != self.index {
+                fs::remove_file(&index_path)?;
+                bail!("Chunk index {} is corrupted", self.index);
+            }
+        }
+        let index = serde_json::from_slice::<Index>(&fs::read(&index_path)?)?;
+
+        let store = download_dir.join(CHUNK_STORE);
+        fs::create_dir_all(&store)?;
+        if let Some(ref seed) = self.seed {
+            match seed_store(&index, seed, &store) {
+                Ok(found) => info!("Reusing {} chunks found on {:?}", found, seed),
+                Err(e) => error!("Unable to seed chunks from {:?}: {}", seed, e),
+            }
+        }
+
+        for chunk in &index.chunks {
+            if !store.join(chunk).exists() {
+                api.download_chunk(product_uid, package_uid, &store, chunk)?;
+            }
+        }
+
+        assemble(&index, &store, &download_dir.join(object))?;
+        fs::remove_dir_all(&store)?;
+
+        Ok(())
+    }
+}
+
+/// Removes from the chunk store of `download_dir` the chunks not listed
+/// by any of the `indexes`, such as the ones left over by an interrupted
+/// download of a previous update package.
+pub(crate) fn prune_store(download_dir: &Path, indexes: &[&str]) -> Result<(), failure::Error> {
+    let store = download_dir.join(CHUNK_STORE);
+    if !store.exists() {
+        return Ok(());
+    }
+
+    let wanted = indexes
+        .iter()
+        .filter_map(|index| fs::read(download_dir.join(index)).ok())
+        .filter_map(|index| serde_json::from_slice::<Index>(&index).ok())
+        .flat_map(|index| index.chunks)
+        .collect::<HashSet<_>>();
+    for entry in fs::read_dir(&store)? {
+        let entry = entry?;
+        if !matches!(entry.file_name().to_str(), Some(chunk) if wanted.contains(chunk)) {
+            fs::remove_file(entry.path())?;
+        }
+    }
+
+    Ok(())
+}
+
+fn sha256sum(path: &Path) -> Result<String, failure::Error> {
+    Ok(hex_digest(Algorithm::SHA256, &fs::read(path)?))
+}
+
+// Stores the chunks of `index` found on `seed` at the same chunk
+// boundaries, returning how many were found.
+fn seed_store(index: &Index, seed: &Path, store: &Path) -> Result<usize, failure::Error> {
+    let wanted = index.chunks.iter().collect::<HashSet<_>>();
+    let mut seed = BufReader::new(fs::File::open(seed)?);
+    let mut buf = Vec::with_capacity(index.chunk_size as usize);
+    let mut found = 0;
+
+    for _ in &index.chunks {
+        buf.clear();
+        seed.by_ref().take(index.chunk_size).read_to_end(&mut buf)?;
+        if buf.is_empty() {
+            break;
+        }
+
+        let chunk = hex_digest(Algorithm::SHA256, &buf);
+        if wanted.contains(&chunk) && !store.join(&chunk).exists() {
+            fs::write(store.join(&chunk), &buf)?;
+            found += 1;
+        }
+    }
+
+    Ok(found)
+}
+
+// Writes the chunks of `index` into `output`. Corrupted chunks are
+// removed from the store so they are downloaded again.
+fn assemble(index: &Index, store: &Path, output: &Path) -> Result<(), failure::Error> {
+    let mut output = BufWriter::new(fs::File::create(output)?);
+
+    for chunk in &index.chunks {
+        let path = store.join(chunk);
+        let data = fs::read(&path)?;
+        if hex_digest(Algorithm::SHA256, &data) != *chunk {
+            fs::remove_file(&path)?;
+            bail!("Chunk {} is corrupted", chunk);
+        }
+
+        output.write_all(&data)?;
+    }
+    output.flush()?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use tempfile::tempdir;
+
+    /// Splits `data` into `chunk_size` chunks, returning the index
+    /// content and the chunks.
+    pub(crate) fn split(data: &[u8], chunk_size: usize) -> (Vec<u8>, Vec<(String, Vec<u8>)>) {
+        let chunks = data
+            .chunks(chunk_size)
+            .map(|c| (hex_digest(Algorithm::SHA256, c), c.to_vec()))
+            .collect::<Vec<_>>();
+        let index = serde_json::json!({
+            "chunk-size": chunk_size,
+            "chunks": chunks.iter().map(|(sha256sum, _)| sha256sum).collect::<Vec<_>>(),
+        });
+
+        (index.to_string().into_bytes(), chunks)
+    }
+
+    fn image(size: usize) -> Vec<u8> {
+        (0..size).map(|i| (i % 251) as u8).collect()
+    }
+
+    #[test]
+    fn seed_from_previous_image() {
+        let old = image(4000);
+        let mut new = old.clone();
+        new[1500] = 0xFF;
+        let (index, chunks) = split(&new, 1024);
+        let index = serde_json::from_slice::<Index>(&index).unwrap();
+
+        let dir = tempdir().unwrap();
+        let seed = dir.path().join("seed");
+        let store = dir.path().join("store");
+        fs::write(&seed, &old).unwrap();
+        fs::create_dir(&store).unwrap();
+
+        assert_eq!(seed_store(&index, &seed, &store).unwrap(), 3);
+        assert!(!store.join(&chunks[1].0).exists());
+        assert!(store.join(&chunks[3].0).exists());
+    }
+
+    #[test]
+    fn assemble_chunks() {
+        let data = image(4000);
+        let (index, chunks) = split(&data, 1024);
+        let index = serde_json::from_slice::<Index>(&index).unwrap();
+
+        let dir = tempdir().unwrap();
+        let output = dir.path().join("output");
+        for (sha256sum, chunk) in &chunks {
+            fs::write(dir.path().join(sha256sum), chunk).unwrap();
+        }
+        assemble(&index, dir.path(), &output).unwrap();
+        assert_eq!(fs::read(&output).unwrap(), data);
+
+        fs::write(dir.path().join(&chunks[2].0), b"corrupted").unwrap();
+        assert!(assemble(&index, dir.path(), &output).is_err());
+        assert!(!dir.path().join(&chunks[2].0).exists());
+    }
+
+    #[test]
+    fn prune_left_over_chunks() {
+        let (index, chunks) = split(&image(4000), 1024);
+        let index_name = hex_digest(Algorithm::SHA256, &index);
+
+        let dir = tempdir().unwrap();
+        let store = dir.path().join(CHUNK_STORE);
+        fs::create_dir(&store).unwrap();
+        fs::write(dir.path().join(&index_name), &index).unwrap();
+        fs::write(store.join(&chunks[0].0), &chunks[0].1).unwrap();
+        fs::write(store.join("left-over"), b"old").unwrap();
+        fs::write(store.join(format!("{}.partial", chunks[1].0)), b"partial").unwrap();
+
+        prune_store(dir.path(), &[&index_name]).unwrap();
+        assert_eq!(fs::read_dir(&store).unwrap().count(), 1);
+        assert!(store.join(&chunks[0].0).exists());
+
+        prune_store(dir.path(), &[]).unwrap();
+        assert_eq!(fs::read_dir(&store).unwrap().count(), 0);
+    }
+}
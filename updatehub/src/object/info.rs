@@ -22,12 +22,19 @@ pub(crate) enum Status {
 impl_object_info!(objects::Copy);
 impl_object_info!(objects::Flash);
 impl_object_info!(objects::Imxkobs);
+impl_object_info!(objects::Mender);
 impl_object_info!(objects::Tarball);
 impl_object_info!(objects::Ubifs);
 impl_object_info!(objects::Raw);
+impl_object_info!(objects::RawDelta);
+impl_object_info!(objects::Shell);
+impl_object_info!(objects::External);
 impl_object_info!(objects::Test);
+impl_object_info!(objects::Zephyr);
 
-impl_object_for_object_types!(Copy, Flash, Imxkobs, Tarball, Ubifs, Raw, Test);
+impl_object_for_object_types!(
+    Copy, External, Flash, Imxkobs, Mender, Tarball, Ubifs, Raw, RawDelta, Shell, Test, Zephyr
+);
 
 pub(crate) trait Info {
     fn status(&self, download_dir: &Path) -> Result<Status, failure::Error> {
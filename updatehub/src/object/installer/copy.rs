@@ -3,85 +3,175 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use crate::{
-    object::{Info, Installer},
-    utils::{self, definitions::TargetTypeExt},
+    object::{
+        installer::{self, Context},
+        Info, Installer,
+    },
+    utils::{self, definitions::TargetTypeExt, fs::MountMode},
 };
-use failure::bail;
+use failure::{bail, ensure};
 use pkg_schema::{definitions, objects};
 use slog_scope::info;
 use std::{
     fs,
     io::{self, Write},
-    os::unix::fs::PermissionsExt,
-    path::Path,
 };
 
 impl Installer for objects::Copy {
-    fn check_requirements(&self) -> Result<(), failure::Error> {
+    fn check_requirements(&self, _: &Context) -> Result<(), failure::Error> {
         info!("'copy' handle checking requirements");
-        if self.compressed {
-            unimplemented!("FIXME: check the required_uncompressed_size");
+        match self.target_type.valid()? {
+            target if target.is_block_device() => {}
+            definitions::TargetType::Path(_) => {
+                ensure!(!self.target_format.should_format, "Path targets cannot be formatted")
+            }
+            _ => bail!("Unexpected target type, expected some device or path."),
         }
 
-        if let definitions::TargetType::Device(_) = self.target_type.valid()? {
+        // A target which is going to be formatted has no filesystem to
+        // check the space of yet.
+        if self.target_format.should_format {
             return Ok(());
         }
 
-        bail!("Unexpected target type, expected some device.")
+        let target_path = self.target_path.strip_prefix("/").unwrap_or(&self.target_path);
+        utils::fs::target_map(
+            &self.target_type,
+            self.filesystem,
+            &self.mount_options,
+            MountMode::ReadOnly,
+            |path| {
+                // The existing file is only replaced once the new one is
+                // complete, so both must fit at the same time.
+                let required =
+                    if self.compressed { self.required_uncompressed_size } else { self.size };
+                Ok(installer::check_space(
+                    &path.join(target_path),
+                    required,
+                    utils::fs::available_space(path)?,
+                )?)
+            },
+        )
     }
 
-    fn install(&self, download_dir: &Path) -> Result<(), failure::Error> {
+    fn should_install(&self) -> Result<bool, failure::Error> {
+        let rule = match self.install_if_different {
+            Some(ref rule) => rule,
+            None => return Ok(true),
+        };
+        if self.target_format.should_format {
+            return Ok(true);
+        }
+
+        let target_path = self.target_path.strip_prefix("/").unwrap_or(&self.target_path);
+
+        utils::fs::target_map(
+            &self.target_type,
+            self.filesystem,
+            &self.mount_options,
+            MountMode::ReadOnly,
+            |path| {
+                let dest = path.join(target_path);
+                if !dest.exists() {
+                    return Ok(true);
+                }
+
+                installer::install_if_different::check(
+                    rule,
+                    self,
+                    self.compressed,
+                    fs::File::open(&dest)?,
+                )
+            },
+        )
+    }
+
+    fn install(&self, context: &Context) -> Result<(), failure::Error> {
         info!("'copy' handler Install");
 
-        let device = self.target_type.get_target()?;
         let filesystem = self.filesystem;
         let mount_options = &self.mount_options;
         let format_options = &self.target_format.format_options;
         let chunk_size = definitions::ChunkSize::default().0;
         let sha256sum = self.sha256sum();
         let target_path = self.target_path.strip_prefix("/").unwrap_or(&self.target_path);
-        let source = download_dir.join(sha256sum);
+        let source = context.download_dir.join(sha256sum);
 
         if self.target_format.should_format {
-            utils::fs::format(&device, filesystem, &format_options)?;
+            utils::fs::format(&self.target_type.get_target()?, filesystem, format_options)?;
         }
 
-        utils::fs::mount_map(&device, filesystem, mount_options, |path| {
-            let dest = path.join(&target_path);
-
-            if self.compressed {
-                unimplemented!("FIXME: uncompress to dest");
-            } else {
-                let mut input = utils::io::timed_buf_reader(chunk_size, fs::File::open(source)?);
-                let mut output = utils::io::timed_buf_writer(
-                    chunk_size,
-                    fs::OpenOptions::new()
-                        .read(true)
-                        .write(true)
-                        .create(true)
-                        .truncate(true)
-                        .open(&dest)?,
-                );
+        utils::fs::target_map(
+            &self.target_type,
+            filesystem,
+            mount_options,
+            MountMode::ReadWrite,
+            |path| {
+                let dest = path.join(&target_path);
+
+                utils::fs::replace_file(&dest, |file, tmp| {
+                    let mut output = utils::io::timed_buf_writer(chunk_size, file.try_clone()?);
+                    if self.compressed {
+                        io::copy(&mut utils::io::uncompressed_reader(&source)?, &mut output)?;
+                    } else {
+                        let mut input =
+                            utils::io::timed_buf_reader(chunk_size, fs::File::open(&source)?);
+                        io::copy(&mut input, &mut output)?;
+                    }
+                    output.flush()?;
+
+                    utils::fs::chown(
+                        tmp,
+                        &self.target_permissions.target_uid,
+                        &self.target_permissions.target_gid,
+                        utils::fs::users_root(&self.target_type, path),
+                    )?;
+
+                    if let Some(mode) = self.target_permissions.target_mode {
+                        utils::fs::chmod(tmp, mode)?;
+                    }
+
+                    Ok(())
+                })?;
 
-                let metadata = dest.metadata()?;
-                let orig_mode = metadata.permissions().mode();
-                metadata.permissions().set_mode(0o100_666);
-                io::copy(&mut input, &mut output)?;
-                output.flush()?;
-                metadata.permissions().set_mode(orig_mode);
-            }
+                Ok(())
+            },
+        )
+    }
 
-            if let Some(mode) = self.target_permissions.target_mode {
-                utils::fs::chmod(&dest, mode)?;
-            }
+    fn verify(&self, context: &Context) -> Result<(), failure::Error> {
+        info!("'copy' handler Verify");
 
-            utils::fs::chown(
-                &dest,
-                &self.target_permissions.target_uid,
-                &self.target_permissions.target_gid,
-            )?;
+        let target_path = self.target_path.strip_prefix("/").unwrap_or(&self.target_path);
+        let source = context.download_dir.join(self.sha256sum());
+
+        utils::fs::target_map(
+            &self.target_type,
+            self.filesystem,
+            &self.mount_options,
+            MountMode::ReadOnly,
+            |path| {
+                let dest = path.join(target_path);
+                let input: Box<dyn io::Read> = if self.compressed {
+                    utils::io::uncompressed_reader(&source)?
+                } else {
+                    Box::new(io::BufReader::new(fs::File::open(&source)?))
+                };
+
+                installer::check_content(&dest, input, io::BufReader::new(fs::File::open(&dest)?))
+            },
+        )
+    }
 
-            Ok(())
+    fn plan(&self, _: &Context) -> Result<installer::Plan, failure::Error> {
+        let path_target = matches!(self.target_type, definitions::TargetType::Path(_));
+        Ok(installer::Plan {
+            target: Some(self.target_type.get_target()?),
+            format: Some(self.filesystem.to_string()).filter(|_| self.target_format.should_format),
+            mount: Some(self.filesystem.to_string()).filter(|_| !path_target),
+            target_path: Some(self.target_path.clone()),
+            write: if self.compressed { self.required_uncompressed_size } else { self.size },
+            ..installer::Plan::default()
         })
     }
 }
@@ -89,7 +179,10 @@ impl Installer for objects::Copy {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::{object::installer::tests::SERIALIZE, utils::definitions::IdExt};
+    use crate::{
+        object::installer::tests::{compress_file, context, SERIALIZE},
+        utils::{definitions::IdExt, fs::CompressKind},
+    };
     use pretty_assertions::assert_eq;
     use std::{
         io::{BufRead, Seek, SeekFrom, Write},
@@ -135,19 +228,26 @@ mod tests {
 
         // When needed, create a file inside the mounted device
         if let Some(perm) = original_permissions {
-            utils::fs::mount_map(&device, definitions::Filesystem::Ext4, &"", |path| {
-                let file = path.join(&"original_file");
-                fs::File::create(&file)?
-                    .write_all(&iter::repeat(ORIGINAL_BYTE).take(FILE_SIZE).collect::<Vec<_>>())?;
-
-                if let Some(mode) = perm.target_mode {
-                    utils::fs::chmod(&file, mode)?;
-                }
-
-                utils::fs::chown(&file, &perm.target_uid, &perm.target_gid)?;
-
-                Ok(())
-            })?;
+            utils::fs::mount_map(
+                &device,
+                definitions::Filesystem::Ext4,
+                &"",
+                MountMode::ReadWrite,
+                |path| {
+                    let file = path.join(&"original_file");
+                    fs::File::create(&file)?.write_all(
+                        &iter::repeat(ORIGINAL_BYTE).take(FILE_SIZE).collect::<Vec<_>>(),
+                    )?;
+
+                    if let Some(mode) = perm.target_mode {
+                        utils::fs::chmod(&file, mode)?;
+                    }
+
+                    utils::fs::chown(&file, &perm.target_uid, &perm.target_gid, path)?;
+
+                    Ok(())
+                },
+            )?;
         }
 
         // Generate base copy object
@@ -169,50 +269,63 @@ mod tests {
         // Change copy object to be used on current test
         f(&mut obj);
 
-        // Peform Install
-        obj.check_requirements()?;
-        obj.setup()?;
-        obj.install(&download_dir.path())?;
+        // Peform Install, releasing the faked device if it fails
+        let mut install = || -> Result<(), failure::Error> {
+            obj.check_requirements(&context(download_dir.path()))?;
+            obj.setup()?;
+            obj.install(&context(download_dir.path()))?;
+            obj.verify(&context(download_dir.path()))
+        };
+        if let Err(e) = install() {
+            loopdev.detach()?;
+            return Err(e);
+        }
 
         // Validade File
-        utils::fs::mount_map(&device, obj.filesystem, &obj.mount_options.clone(), |path| {
-            let chunk_size = definitions::ChunkSize::default().0;
-            let dest = path.join(&obj.target_path);
-            let source = download_dir.path().join(&obj.sha256sum);
-            let mut rd1 = io::BufReader::with_capacity(chunk_size, fs::File::open(&source)?);
-            let mut rd2 = io::BufReader::with_capacity(chunk_size, fs::File::open(&dest)?);
-
-            loop {
-                let buf1 = rd1.fill_buf()?;
-                let len1 = buf1.len();
-                let buf2 = rd2.fill_buf()?;
-                let len2 = buf2.len();
-                // Stop comparing when both the files reach EOF
-                if len1 == 0 && len2 == 0 {
-                    break;
+        utils::fs::mount_map(
+            &device,
+            obj.filesystem,
+            &obj.mount_options.clone(),
+            MountMode::ReadOnly,
+            |path| {
+                let chunk_size = definitions::ChunkSize::default().0;
+                let dest = path.join(&obj.target_path);
+                let mut rd1 =
+                    io::BufReader::with_capacity(chunk_size, fs::File::open(source.path())?);
+                let mut rd2 = io::BufReader::with_capacity(chunk_size, fs::File::open(&dest)?);
+
+                loop {
+                    let buf1 = rd1.fill_buf()?;
+                    let len1 = buf1.len();
+                    let buf2 = rd2.fill_buf()?;
+                    let len2 = buf2.len();
+                    // Stop comparing when both the files reach EOF
+                    if len1 == 0 && len2 == 0 {
+                        break;
+                    }
+                    assert_eq!(buf1, buf2);
+                    rd1.consume(len1);
+                    rd2.consume(len2);
                 }
-                assert_eq!(buf1, buf2);
-                rd1.consume(len1);
-                rd2.consume(len2);
-            }
 
-            let metadata = dest.metadata()?;
-            if let Some(mode) = obj.target_permissions.target_mode {
-                assert_eq!(mode, metadata.mode() % 0o1000);
-            };
+                let metadata = dest.metadata()?;
+                if let Some(mode) = obj.target_permissions.target_mode {
+                    assert_eq!(mode, metadata.mode() % 0o1000);
+                };
 
-            if let Some(uid) = obj.target_permissions.target_uid {
-                let uid = uid.as_u32();
-                assert_eq!(uid, metadata.uid());
-            };
+                if let Some(uid) = obj.target_permissions.target_uid {
+                    let uid = uid.resolve(path)?;
+                    assert_eq!(uid, metadata.uid());
+                };
 
-            if let Some(gid) = obj.target_permissions.target_gid {
-                let gid = gid.as_u32();
-                assert_eq!(gid, metadata.gid());
-            };
+                if let Some(gid) = obj.target_permissions.target_gid {
+                    let gid = gid.resolve(path)?;
+                    assert_eq!(gid, metadata.gid());
+                };
 
-            Ok(())
-        })?;
+                Ok(())
+            },
+        )?;
 
         loopdev.detach()?;
 
@@ -282,4 +395,71 @@ mod tests {
         )
         .unwrap();
     }
+
+    #[test]
+    #[ignore]
+    fn copy_larger_than_filesystem() {
+        assert!(exec_test_with_copy(|obj| obj.size = 2 * 1024 * 1024, None).is_err());
+    }
+
+    #[test]
+    #[ignore]
+    fn copy_compressed_file() {
+        // The compressed file is removed once the test is done
+        let mut compressed = None;
+        exec_test_with_copy(
+            |obj| {
+                let file =
+                    compress_file(&PathBuf::from(&obj.sha256sum), CompressKind::GZip).unwrap();
+                obj.compressed = true;
+                obj.sha256sum = file.path().to_string_lossy().to_string();
+                compressed = Some(file);
+            },
+            None,
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn copy_into_path() {
+        let download_dir = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        let mut source = tempfile::NamedTempFile::new_in(download_dir.path()).unwrap();
+        source.write_all(&[DEFAULT_BYTE; FILE_SIZE]).unwrap();
+
+        let mut obj = objects::Copy {
+            filename: "".to_string(),
+            filesystem: definitions::Filesystem::Ext4,
+            size: FILE_SIZE as u64,
+            sha256sum: source.path().to_string_lossy().to_string(),
+            target_type: definitions::TargetType::Path(target.path().to_path_buf()),
+            target_path: PathBuf::from("/app/data"),
+            install_if_different: None,
+            target_permissions: definitions::TargetPermissions {
+                target_mode: Some(0o4750),
+                target_uid: Some(definitions::target_permissions::Uid::Name("root".to_string())),
+                target_gid: Some(definitions::target_permissions::Gid::Name("root".to_string())),
+            },
+            compressed: false,
+            required_uncompressed_size: 0,
+            target_format: definitions::TargetFormat::default(),
+            mount_options: String::default(),
+        };
+
+        obj.check_requirements(&context(download_dir.path())).unwrap();
+        obj.install(&context(download_dir.path())).unwrap();
+        obj.verify(&context(download_dir.path())).unwrap();
+
+        let dest = target.path().join("app/data");
+        assert_eq!(fs::read(&dest).unwrap(), fs::read(source.path()).unwrap());
+        assert_eq!(dest.metadata().unwrap().mode() % 0o10000, 0o4750);
+        assert_eq!(dest.metadata().unwrap().uid(), 0);
+        assert_eq!(dest.metadata().unwrap().gid(), 0);
+
+        obj.size = u64::MAX;
+        assert!(obj.check_requirements(&context(download_dir.path())).is_err());
+
+        obj.target_format.should_format = true;
+        assert!(obj.check_requirements(&context(download_dir.path())).is_err());
+    }
 }
@@ -0,0 +1,215 @@
+// Copyright (C) 2019 O.S. Systems Sofware LTDA
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::object::{
+    installer::{self, Context},
+    Info, Installer,
+};
+use failure::{bail, ensure};
+use pkg_schema::objects;
+use slog_scope::{error, info};
+use std::{
+    path::{Component, Path, PathBuf},
+    process::Command,
+};
+
+// Directory, inside the metadata path, holding the executables which
+// handle the install modes unknown to the agent. Each handler is named
+// after the mode it handles.
+const INSTALL_HANDLERS_DIR: &str = "install-handlers.d";
+
+// Gets the handler of the object's mode. The mode comes from the server
+// so it must be a plain file name, which cannot point outside of the
+// handlers directory.
+fn handler(obj: &objects::External, context: &Context) -> Result<PathBuf, failure::Error> {
+    let mut components = Path::new(&obj.mode).components();
+    match (components.next(), components.next()) {
+        (Some(Component::Normal(_)), None) if !obj.mode.contains('/') => {}
+        _ => bail!("Invalid install mode: {:?}", obj.mode),
+    }
+
+    Ok(context.metadata_path.join(INSTALL_HANDLERS_DIR).join(&obj.mode))
+}
+
+// Runs the handler of the object's mode with `step` as argument. The
+// object, as found in the package metadata, is available to it in the
+// `UPDATEHUB_OBJECT` environment variable.
+fn run_handler(
+    obj: &objects::External,
+    step: &str,
+    context: &Context,
+) -> Result<(), failure::Error> {
+    let output = Command::new(handler(obj, context)?)
+        .arg(step)
+        .current_dir(&context.download_dir)
+        .env("UPDATEHUB_OBJECT", serde_json::to_string(obj)?)
+        .env("UPDATEHUB_OBJECT_PATH", context.download_dir.join(obj.sha256sum()))
+        .env("UPDATEHUB_DOWNLOAD_DIR", &context.download_dir)
+        .env("UPDATEHUB_INSTALLATION_SET", context.installation_set.to_string())
+        .env("UPDATEHUB_PACKAGE_UID", &context.package_uid)
+        .env("UPDATEHUB_OBJECT_INDEX", context.object_index.to_string())
+        .output()?;
+
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .for_each(|line| info!("{} {} (stdout): {}", obj.mode, step, line));
+    String::from_utf8_lossy(&output.stderr)
+        .lines()
+        .for_each(|line| error!("{} {} (stderr): {}", obj.mode, step, line));
+    ensure!(output.status.success(), "{} handler failed to {}: {}", obj.mode, step, output.status);
+
+    Ok(())
+}
+
+impl Installer for objects::External {
+    fn check_requirements(&self, context: &Context) -> Result<(), failure::Error> {
+        info!("'{}' external handler checking requirements", self.mode);
+
+        ensure!(
+            handler(self, context)?.is_file(),
+            "No handler found for '{}' install mode",
+            self.mode
+        );
+
+        run_handler(self, "check-requirements", context)
+    }
+
+    fn install(&self, context: &Context) -> Result<(), failure::Error> {
+        info!("'{}' external handler Install", self.mode);
+        run_handler(self, "install", context)
+    }
+
+    fn cleanup(&mut self, context: &Context) -> Result<(), failure::Error> {
+        info!("'{}' external handler Cleanup", self.mode);
+        run_handler(self, "cleanup", context)
+    }
+
+    fn plan(&self, context: &Context) -> Result<installer::Plan, failure::Error> {
+        Ok(installer::Plan {
+            target: self.properties.get("target").and_then(|t| t.as_str()).map(PathBuf::from),
+            write: self.size,
+            run: Some(handler(self, context)?),
+            ..installer::Plan::default()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::object::installer::tests::context;
+    use pretty_assertions::assert_eq;
+    use serde_json::json;
+    use std::{fs, os::unix::fs::PermissionsExt, path::Path};
+    use tempfile::tempdir;
+
+    fn fake_external_obj() -> objects::External {
+        serde_json::from_value(json!({
+            "mode": "vendor",
+            "filename": "firmware.bin",
+            "size": 1024,
+            "sha256sum": "cfe2be1c64b0387500853de0f48303e3de7b1c6f1508dc719eeafa0d41c36722",
+            "target": "fpga0",
+        }))
+        .unwrap()
+    }
+
+    fn fake_context(download_dir: &Path, metadata_path: &Path, handler: &str) -> Context {
+        let handlers = metadata_path.join(INSTALL_HANDLERS_DIR);
+        fs::create_dir(&handlers).unwrap();
+        fs::write(handlers.join("vendor"), handler).unwrap();
+        fs::set_permissions(handlers.join("vendor"), fs::Permissions::from_mode(0o755)).unwrap();
+
+        Context { metadata_path: metadata_path.to_path_buf(), ..context(download_dir) }
+    }
+
+    #[test]
+    fn run_handler_steps() {
+        let download_dir = tempdir().unwrap();
+        let metadata_path = tempdir().unwrap();
+        let context = fake_context(
+            download_dir.path(),
+            metadata_path.path(),
+            "#!/bin/sh\necho $1 $UPDATEHUB_OBJECT_PATH >> calls\necho $UPDATEHUB_OBJECT > object\n",
+        );
+        let mut obj = fake_external_obj();
+
+        obj.check_requirements(&context).unwrap();
+        obj.install(&context).unwrap();
+        obj.cleanup(&context).unwrap();
+
+        let path = download_dir.path().join(obj.sha256sum());
+        assert_eq!(
+            fs::read_to_string(download_dir.path().join("calls")).unwrap(),
+            format!("check-requirements {0}\ninstall {0}\ncleanup {0}\n", path.display())
+        );
+        assert_eq!(
+            serde_json::from_str::<objects::External>(
+                &fs::read_to_string(download_dir.path().join("object")).unwrap()
+            )
+            .unwrap(),
+            obj
+        );
+    }
+
+    #[test]
+    fn missing_handler() {
+        let download_dir = tempdir().unwrap();
+        let metadata_path = tempdir().unwrap();
+        let context = Context {
+            metadata_path: metadata_path.path().to_path_buf(),
+            ..context(download_dir.path())
+        };
+
+        assert!(fake_external_obj().check_requirements(&context).is_err());
+    }
+
+    #[test]
+    fn failing_handler() {
+        let download_dir = tempdir().unwrap();
+        let metadata_path = tempdir().unwrap();
+        let context = fake_context(
+            download_dir.path(),
+            metadata_path.path(),
+            "#!/bin/sh\n[ \"$1\" != install ] || exit 1\n",
+        );
+        let obj = fake_external_obj();
+
+        obj.check_requirements(&context).unwrap();
+        assert!(obj.install(&context).is_err());
+    }
+
+    #[test]
+    fn plan_runs_handler() {
+        let download_dir = tempdir().unwrap();
+        let metadata_path = tempdir().unwrap();
+        let context = Context {
+            metadata_path: metadata_path.path().to_path_buf(),
+            ..context(download_dir.path())
+        };
+
+        assert_eq!(
+            fake_external_obj().plan(&context).unwrap(),
+            installer::Plan {
+                target: Some(PathBuf::from("fpga0")),
+                write: 1024,
+                run: Some(metadata_path.path().join(INSTALL_HANDLERS_DIR).join("vendor")),
+                ..installer::Plan::default()
+            }
+        );
+    }
+
+    #[test]
+    fn mode_outside_handlers_dir() {
+        let download_dir = tempdir().unwrap();
+        let metadata_path = tempdir().unwrap();
+        let context = fake_context(download_dir.path(), metadata_path.path(), "#!/bin/sh\n");
+
+        for mode in &["../../../usr/bin/foo", "/bin/sh", "vendor/../vendor", "..", ""] {
+            let obj = objects::External { mode: mode.to_string(), ..fake_external_obj() };
+            assert!(obj.check_requirements(&context).is_err(), "Accepted {:?} mode", mode);
+            assert!(obj.install(&context).is_err(), "Accepted {:?} mode", mode);
+        }
+    }
+}
@@ -3,20 +3,20 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use crate::{
-    object::{Info, Installer},
+    object::{
+        installer::{self, Context},
+        Info, Installer,
+    },
     utils::{self, definitions::TargetTypeExt},
 };
 use failure::bail;
 use pkg_schema::{definitions, objects};
 use slog_scope::info;
+use std::{fs, io::BufReader};
 
 impl Installer for objects::Flash {
-    fn check_requirements(&self) -> Result<(), failure::Error> {
+    fn check_requirements(&self, _: &Context) -> Result<(), failure::Error> {
         info!("'flash' handle checking requirements");
-        utils::fs::is_executable_in_path("nandwrite")?;
-        utils::fs::is_executable_in_path("flashcp")?;
-        utils::fs::is_executable_in_path("flash_erase")?;
-
         match self.target {
             definitions::TargetType::Device(_) | definitions::TargetType::MTDName(_) => {
                 self.target.valid().map(|_| ())
@@ -25,34 +25,58 @@ impl Installer for objects::Flash {
         }
     }
 
-    fn install(&self, download_dir: &std::path::Path) -> Result<(), failure::Error> {
+    fn should_install(&self) -> Result<bool, failure::Error> {
+        match self.install_if_different {
+            Some(ref rule) => installer::install_if_different::check(
+                rule,
+                self,
+                false,
+                fs::File::open(self.target.get_target()?)?,
+            ),
+            None => Ok(true),
+        }
+    }
+
+    fn install(&self, context: &Context) -> Result<(), failure::Error> {
         info!("'flash' handler Install");
 
         let target = self.target.get_target()?;
-        let source = download_dir.join(self.sha256sum());
-        let is_nand = utils::mtd::is_nand(&target)?;
+        let source = context.download_dir.join(self.sha256sum());
+        let mut device = utils::mtd::MtdDevice::open(&target)?;
 
-        easy_process::run(&format!("flash_erase {:?} 0 0", target))?;
-
-        if is_nand {
-            easy_process::run(&format!("nandwrite -p {:?} {:?}", target, source))?;
-        } else {
-            easy_process::run(&format!("flashcp {:?} {:?}", source, target))?;
-        }
+        utils::mtd::write_image(&mut device, BufReader::new(fs::File::open(source)?))?;
 
         Ok(())
     }
+
+    fn verify(&self, context: &Context) -> Result<(), failure::Error> {
+        info!("'flash' handler Verify");
+
+        let target = self.target.get_target()?;
+        let source = context.download_dir.join(self.sha256sum());
+        let mut device = utils::mtd::MtdDevice::open(&target)?;
+
+        utils::mtd::verify_image(&mut device, BufReader::new(fs::File::open(source)?))
+    }
+
+    fn plan(&self, _: &Context) -> Result<installer::Plan, failure::Error> {
+        Ok(installer::Plan {
+            target: Some(self.target.get_target()?),
+            write: self.size,
+            ..installer::Plan::default()
+        })
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::{
-        object::installer::tests::create_echo_bins,
+        object::installer::tests::context,
         utils::mtd::tests::{FakeMtd, MtdKind, SERIALIZE},
     };
     use pretty_assertions::assert_eq;
-    use std::env;
+    use std::io::Read;
 
     fn fake_flash_obj(target: &str) -> objects::Flash {
         objects::Flash {
@@ -65,57 +89,32 @@ mod tests {
         }
     }
 
-    #[test]
-    fn check_requirements_with_missing_binaries() {
+    fn exec_install(kind: MtdKind) {
+        let _mtd_lock = SERIALIZE.lock();
+        let mtd = FakeMtd::new(&["system0"], kind).unwrap();
         let flash_obj = fake_flash_obj("system0");
+        let download_dir = tempfile::tempdir().unwrap();
+        let image = (0..5000).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+        fs::write(download_dir.path().join(&flash_obj.sha256sum), &image).unwrap();
 
-        env::set_var("PATH", "");
-        let (_handle, _) = create_echo_bins(&["flash_erase"]).unwrap();
-        assert!(flash_obj.check_requirements().is_err());
-
-        env::set_var("PATH", "");
-        let (_handle, _) = create_echo_bins(&["flashcp"]).unwrap();
-        assert!(flash_obj.check_requirements().is_err());
-
-        env::set_var("PATH", "");
-        let (_handle, _) = create_echo_bins(&["nandwrite"]).unwrap();
-        assert!(flash_obj.check_requirements().is_err());
-
-        env::set_var("PATH", "");
-        let (_handle, _) = create_echo_bins(&["flash_erase", "nandwrite"]).unwrap();
-        assert!(flash_obj.check_requirements().is_err());
-
-        env::set_var("PATH", "");
-        let (_handle, _) = create_echo_bins(&["flash_erase", "flashcp"]).unwrap();
-        assert!(flash_obj.check_requirements().is_err());
+        flash_obj.check_requirements(&context(download_dir.path())).unwrap();
+        flash_obj.install(&context(download_dir.path())).unwrap();
+        flash_obj.verify(&context(download_dir.path())).unwrap();
 
-        env::set_var("PATH", "");
-        let (_handle, _) = create_echo_bins(&["nandwrite", "nandwrite"]).unwrap();
-        assert!(flash_obj.check_requirements().is_err());
+        let mut content = vec![0; image.len()];
+        fs::File::open(&mtd.devices[0]).unwrap().read_exact(&mut content).unwrap();
+        assert_eq!(content, image);
     }
 
     #[test]
     #[ignore]
     fn install_nor() {
-        let _mtd_lock = SERIALIZE.lock();
-        let mtd = FakeMtd::new(&["system0"], MtdKind::Nor).unwrap();
-        let target = &mtd.devices[0];
-        let flash_obj = fake_flash_obj("system0");
-        let download_dir = tempfile::tempdir().unwrap();
-        let source = download_dir.path().join(&flash_obj.sha256sum);
-
-        let (_handle, calls) = create_echo_bins(&["flash_erase", "flashcp", "nandwrite"]).unwrap();
-
-        flash_obj.check_requirements().unwrap();
-        flash_obj.install(download_dir.path()).unwrap();
-
-        let expected = format!(
-            "flash_erase {} 0 0\nflashcp {} {}\n",
-            target.to_str().unwrap(),
-            source.to_str().unwrap(),
-            target.to_str().unwrap()
-        );
+        exec_install(MtdKind::Nor);
+    }
 
-        assert_eq!(std::fs::read_to_string(calls).unwrap(), expected);
+    #[test]
+    #[ignore]
+    fn install_nand() {
+        exec_install(MtdKind::Nand);
     }
 }
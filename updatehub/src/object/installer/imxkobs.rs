@@ -3,23 +3,44 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use crate::{
-    object::{Info, Installer},
+    object::{
+        installer::{self, Context},
+        Info, Installer,
+    },
     utils,
 };
 use easy_process;
 use failure::format_err;
 use pkg_schema::objects;
 use slog_scope::info;
+use std::{fs, path::Path};
+
+/// kobs-ng writes to the first chip unless told otherwise.
+fn target(obj: &objects::Imxkobs) -> &Path {
+    obj.chip_0_device_path.as_deref().unwrap_or_else(|| Path::new("/dev/mtd0"))
+}
 
 impl Installer for objects::Imxkobs {
-    fn check_requirements(&self) -> Result<(), failure::Error> {
+    fn check_requirements(&self, _: &Context) -> Result<(), failure::Error> {
         info!("'imxkobs' handle checking requirements");
         utils::fs::is_executable_in_path("kobs-ng")?;
 
         Ok(())
     }
 
-    fn install(&self, download_dir: &std::path::Path) -> Result<(), failure::Error> {
+    fn should_install(&self) -> Result<bool, failure::Error> {
+        match self.install_if_different {
+            Some(ref rule) => installer::install_if_different::check(
+                rule,
+                self,
+                false,
+                fs::File::open(target(self))?,
+            ),
+            None => Ok(true),
+        }
+    }
+
+    fn install(&self, context: &Context) -> Result<(), failure::Error> {
         info!("'imxkobs' handler Install");
         let mut cmd = String::from("kobs-ng init ");
 
@@ -27,7 +48,8 @@ impl Installer for objects::Imxkobs {
             cmd += "-x "
         };
 
-        cmd += download_dir
+        cmd += context
+            .download_dir
             .join(self.sha256sum())
             .to_str()
             .ok_or_else(|| format_err!("Unable to get source path"))?;
@@ -49,14 +71,27 @@ impl Installer for objects::Imxkobs {
         easy_process::run(&cmd)?;
         Ok(())
     }
+
+    fn plan(&self, _: &Context) -> Result<installer::Plan, failure::Error> {
+        Ok(installer::Plan {
+            target: self.chip_0_device_path.clone(),
+            write: self.size,
+            ..installer::Plan::default()
+        })
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::object::installer::tests::create_echo_bins;
+    use crate::object::installer::tests::{context, create_echo_bins};
+    use pkg_schema::definitions;
     use pretty_assertions::assert_eq;
-    use std::{env, path::PathBuf};
+    use std::{
+        env,
+        io::Write,
+        path::{Path, PathBuf},
+    };
 
     fn fake_imxkobs_obj() -> objects::Imxkobs {
         objects::Imxkobs {
@@ -77,7 +112,7 @@ mod tests {
         let imxkobs_obj = fake_imxkobs_obj();
 
         env::set_var("PATH", "");
-        assert!(imxkobs_obj.check_requirements().is_err());
+        assert!(imxkobs_obj.check_requirements(&context(Path::new("/"))).is_err());
     }
 
     #[test]
@@ -92,8 +127,8 @@ mod tests {
 
         let (_handle, calls) = create_echo_bins(&["kobs-ng"]).unwrap();
 
-        imxkobs_obj.check_requirements().unwrap();
-        imxkobs_obj.install(download_dir.path()).unwrap();
+        imxkobs_obj.check_requirements(&context(download_dir.path())).unwrap();
+        imxkobs_obj.install(&context(download_dir.path())).unwrap();
 
         let expected = format!("kobs-ng init {} -v\n", source.to_str().unwrap());
         assert_eq!(std::fs::read_to_string(calls).unwrap(), expected);
@@ -110,8 +145,8 @@ mod tests {
 
         let (_handle, calls) = create_echo_bins(&["kobs-ng"]).unwrap();
 
-        imxkobs_obj.check_requirements().unwrap();
-        imxkobs_obj.install(download_dir.path()).unwrap();
+        imxkobs_obj.check_requirements(&context(download_dir.path())).unwrap();
+        imxkobs_obj.install(&context(download_dir.path())).unwrap();
 
         let expected = format!("kobs-ng init -x {} -v\n", source.to_str().unwrap());
         assert_eq!(std::fs::read_to_string(calls).unwrap(), expected);
@@ -128,8 +163,8 @@ mod tests {
 
         let (_handle, calls) = create_echo_bins(&["kobs-ng"]).unwrap();
 
-        imxkobs_obj.check_requirements().unwrap();
-        imxkobs_obj.install(download_dir.path()).unwrap();
+        imxkobs_obj.check_requirements(&context(download_dir.path())).unwrap();
+        imxkobs_obj.install(&context(download_dir.path())).unwrap();
 
         let expected = format!(
             "kobs-ng init {} --search_exponent={} -v\n",
@@ -150,8 +185,8 @@ mod tests {
 
         let (_handle, calls) = create_echo_bins(&["kobs-ng"]).unwrap();
 
-        imxkobs_obj.check_requirements().unwrap();
-        imxkobs_obj.install(download_dir.path()).unwrap();
+        imxkobs_obj.check_requirements(&context(download_dir.path())).unwrap();
+        imxkobs_obj.install(&context(download_dir.path())).unwrap();
 
         let expected = format!(
             "kobs-ng init {} --chip_0_device_path={} -v\n",
@@ -172,8 +207,8 @@ mod tests {
 
         let (_handle, calls) = create_echo_bins(&["kobs-ng"]).unwrap();
 
-        imxkobs_obj.check_requirements().unwrap();
-        imxkobs_obj.install(download_dir.path()).unwrap();
+        imxkobs_obj.check_requirements(&context(download_dir.path())).unwrap();
+        imxkobs_obj.install(&context(download_dir.path())).unwrap();
 
         let expected = format!(
             "kobs-ng init {} --chip_1_device_path={} -v\n",
@@ -191,8 +226,8 @@ mod tests {
 
         let (_handle, calls) = create_echo_bins(&["kobs-ng"]).unwrap();
 
-        imxkobs_obj.check_requirements().unwrap();
-        imxkobs_obj.install(download_dir.path()).unwrap();
+        imxkobs_obj.check_requirements(&context(download_dir.path())).unwrap();
+        imxkobs_obj.install(&context(download_dir.path())).unwrap();
 
         let expected = format!(
             "kobs-ng init -x {} --search_exponent={} --chip_0_device_path={} --chip_1_device_path={} -v\n",
@@ -203,4 +238,21 @@ mod tests {
         );
         assert_eq!(std::fs::read_to_string(calls).unwrap(), expected);
     }
+
+    #[test]
+    fn install_if_different_checksum() {
+        let mut imxkobs_obj = fake_imxkobs_obj();
+        let mut target = tempfile::NamedTempFile::new().unwrap();
+        let content = vec![0xF0; imxkobs_obj.size as usize];
+        let mut hasher = crypto_hash::Hasher::new(crypto_hash::Algorithm::SHA256);
+        hasher.write_all(&content).unwrap();
+        imxkobs_obj.sha256sum = hex::encode(hasher.finish());
+        imxkobs_obj.chip_0_device_path = Some(target.path().to_owned());
+        imxkobs_obj.install_if_different = Some(definitions::InstallIfDifferent::CheckSum);
+
+        assert!(imxkobs_obj.should_install().unwrap());
+
+        target.write_all(&content).unwrap();
+        assert!(!imxkobs_obj.should_install().unwrap());
+    }
 }
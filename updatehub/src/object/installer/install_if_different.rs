@@ -0,0 +1,357 @@
+// Copyright (C) 2019 O.S. Systems Sofware LTDA
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::object::Info;
+use crypto_hash::{Algorithm, Hasher};
+use flate2::read::GzDecoder;
+use pkg_schema::definitions::{
+    install_if_different::{KnownPatternKind, Pattern},
+    InstallIfDifferent,
+};
+use regex::bytes::Regex;
+use slog_scope::info;
+use std::{
+    convert::TryInto,
+    io::{self, Cursor, Read, Seek, SeekFrom},
+};
+
+// Amount of data read from the target to identify the kernel image
+// format. It also covers the version string of x86 bzImages.
+const KERNEL_HEADER_SIZE: u64 = 64 * 1024;
+// U-Boot binaries are small, so we avoid scanning a whole device when
+// it is installed in a raw device.
+const UBOOT_SCAN_LIMIT: u64 = 4 * 1024 * 1024;
+const SCAN_CHUNK_SIZE: usize = 64 * 1024;
+// Tail of each scanned chunk kept so matches crossing chunk
+// boundaries are still found.
+const SCAN_OVERLAP: usize = 256;
+
+/// Checks the `install-if-different` rule against the current
+/// `target` contents, starting from its current position, returning
+/// whether the object still needs to be installed.
+pub(crate) fn check<R: Read + Seek>(
+    rule: &InstallIfDifferent,
+    object: &impl Info,
+    compressed: bool,
+    target: R,
+) -> Result<bool, failure::Error> {
+    match rule {
+        InstallIfDifferent::CheckSum => {
+            // The checksum of compressed objects covers the compressed
+            // stream, which is never what ends up on target.
+            if compressed {
+                info!("Checksum can not be compared for compressed objects, installing");
+                return Ok(true);
+            }
+
+            let current = sha256sum(target, object.len())?;
+            info!("Object checksum: {}, target checksum: {}", object.sha256sum(), current);
+            Ok(current != object.sha256sum())
+        }
+        InstallIfDifferent::KnownPattern { version, pattern } => {
+            let current = match pattern {
+                KnownPatternKind::LinuxKernel => linux_kernel_version(target)?,
+                KnownPatternKind::UBoot => uboot_version(target)?,
+            };
+            info!("Object version: {}, target version: {:?}", version, current);
+            Ok(current.as_ref() != Some(version))
+        }
+        InstallIfDifferent::CustomPattern { version, pattern } => {
+            let current = custom_pattern_version(target, pattern)?;
+            info!("Object version: {}, target version: {:?}", version, current);
+            Ok(current.as_ref() != Some(version))
+        }
+    }
+}
+
+fn linux_kernel_version<R: Read>(mut target: R) -> io::Result<Option<String>> {
+    let mut header = Vec::new();
+    target.by_ref().take(KERNEL_HEADER_SIZE).read_to_end(&mut header)?;
+
+    // U-Boot legacy uImage, whose image name is set by the kernel build
+    // as 'Linux-<version>'.
+    if header.starts_with(&[0x27, 0x05, 0x19, 0x56]) && header.len() >= 64 {
+        let name = header[32..64].split(|b| *b == 0).next().unwrap_or_default();
+        return Ok(capture_version(&Regex::new(r"^Linux-(\S+)").unwrap(), name));
+    }
+
+    // x86 bzImage, which points to the version string in its setup
+    // header.
+    if header.get(0x202..0x206) == Some(b"HdrS") {
+        let offset = match header.get(0x20E..0x210) {
+            Some(offset) => u16::from_le_bytes(offset.try_into().unwrap()) as usize + 0x200,
+            None => return Ok(None),
+        };
+        return Ok(header
+            .get(offset..)
+            .and_then(|s| capture_version(&Regex::new(r"^([^\s\x00]+)").unwrap(), s)));
+    }
+
+    let banner = Regex::new(r"Linux version (\S+) ").unwrap();
+
+    // arm64 Image, which is not compressed and carries its effective size
+    // in the header.
+    if header.get(0x38..0x3C) == Some(b"ARM\x64") {
+        let image_size = u64::from_le_bytes(header[0x10..0x18].try_into().unwrap());
+        return find_in_stream(Cursor::new(header).chain(target).take(image_size), &banner);
+    }
+
+    // ARM zImage, where the kernel follows the decompressor. Only gzip
+    // compressed kernels are supported.
+    if header.get(0x24..0x28) == Some(&[0x18, 0x28, 0x6F, 0x01]) {
+        let payload = match header.windows(3).position(|w| w == [0x1F, 0x8B, 0x08]) {
+            Some(payload) => payload,
+            None => return Ok(None),
+        };
+        let kernel = GzDecoder::new(Cursor::new(header.split_off(payload)).chain(target));
+
+        // An image which fails to decompress surely differs from the
+        // object, so we don't fail the installation because of it.
+        return Ok(find_in_stream(kernel, &banner).unwrap_or(None));
+    }
+
+    Ok(None)
+}
+
+fn uboot_version<R: Read>(target: R) -> io::Result<Option<String>> {
+    find_in_stream(target.take(UBOOT_SCAN_LIMIT), &Regex::new(r"U-Boot(?: SPL)? (\S+) \(").unwrap())
+}
+
+fn custom_pattern_version<R: Read + Seek>(
+    mut target: R,
+    pattern: &Pattern,
+) -> Result<Option<String>, failure::Error> {
+    let re = Regex::new(&pattern.regexp)?;
+
+    let mut buf = Vec::new();
+    target.seek(SeekFrom::Current(pattern.seek as i64))?;
+    target.take(pattern.buffer_size).read_to_end(&mut buf)?;
+
+    // The version is the first capture group, when there is one, or the
+    // whole match otherwise.
+    Ok(re
+        .captures(&buf)
+        .and_then(|c| c.get(1).or_else(|| c.get(0)))
+        .map(|m| String::from_utf8_lossy(m.as_bytes()).into_owned()))
+}
+
+fn find_in_stream<R: Read>(mut reader: R, re: &Regex) -> io::Result<Option<String>> {
+    let mut window = Vec::with_capacity(SCAN_CHUNK_SIZE + SCAN_OVERLAP);
+    let mut chunk = vec![0; SCAN_CHUNK_SIZE];
+
+    loop {
+        let len = reader.read(&mut chunk)?;
+        if len == 0 {
+            return Ok(None);
+        }
+
+        window.extend_from_slice(&chunk[..len]);
+        if let Some(version) = capture_version(re, &window) {
+            return Ok(Some(version));
+        }
+
+        let keep = window.len().min(SCAN_OVERLAP);
+        window.drain(..window.len() - keep);
+    }
+}
+
+fn capture_version(re: &Regex, buf: &[u8]) -> Option<String> {
+    re.captures(buf)?.get(1).map(|m| String::from_utf8_lossy(m.as_bytes()).into_owned())
+}
+
+fn sha256sum<R: Read>(reader: R, size: u64) -> io::Result<String> {
+    let mut hasher = Hasher::new(Algorithm::SHA256);
+    io::copy(&mut reader.take(size), &mut hasher)?;
+
+    Ok(hex::encode(hasher.finish()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pkg_schema::objects;
+    use std::io::Cursor;
+
+    const CONTENT: &[u8] = b"some content";
+    const CONTENT_SHA256SUM: &str =
+        "290f493c44f5d63d06b374d0a5abd292fae38b92cab2fae5efefe1b0e9347f56";
+
+    fn fake_test_object(sha256sum: &str) -> objects::Test {
+        objects::Test {
+            filename: "test".to_string(),
+            size: CONTENT.len() as u64,
+            sha256sum: sha256sum.to_string(),
+            target: "/dev/null".to_string(),
+        }
+    }
+
+    #[test]
+    fn checksum_matches() {
+        let obj = fake_test_object(CONTENT_SHA256SUM);
+        let target = Cursor::new([CONTENT, b"trailing data"].concat());
+
+        assert!(!check(&InstallIfDifferent::CheckSum, &obj, false, target).unwrap());
+    }
+
+    #[test]
+    fn checksum_differs() {
+        let obj = fake_test_object(CONTENT_SHA256SUM);
+
+        assert!(check(&InstallIfDifferent::CheckSum, &obj, false, Cursor::new(b"other")).unwrap());
+    }
+
+    fn known_pattern(version: &str, pattern: KnownPatternKind) -> InstallIfDifferent {
+        InstallIfDifferent::KnownPattern { version: version.to_string(), pattern }
+    }
+
+    fn fake_uimage(name: &str) -> Vec<u8> {
+        let mut image = vec![0; 128];
+        image[..4].copy_from_slice(&[0x27, 0x05, 0x19, 0x56]);
+        image[32..32 + name.len()].copy_from_slice(name.as_bytes());
+        image
+    }
+
+    fn fake_bzimage(version: &str) -> Vec<u8> {
+        let mut image = vec![0; 0x1000];
+        image[0x202..0x206].copy_from_slice(b"HdrS");
+        image[0x20E..0x210].copy_from_slice(&0x0800u16.to_le_bytes());
+        image[0xA00..0xA00 + version.len()].copy_from_slice(version.as_bytes());
+        image
+    }
+
+    fn fake_arm64_image(version: &str) -> Vec<u8> {
+        // Place the banner far enough to cross a few scanning chunks
+        let mut image = vec![0; 3 * SCAN_CHUNK_SIZE];
+        let banner = format!("Linux version {} (builder@host) #1 SMP", version);
+        let offset = 2 * SCAN_CHUNK_SIZE - banner.len() / 2;
+        image[offset..offset + banner.len()].copy_from_slice(banner.as_bytes());
+        let image_size = image.len() as u64;
+        image[0x10..0x18].copy_from_slice(&image_size.to_le_bytes());
+        image[0x38..0x3C].copy_from_slice(b"ARM\x64");
+        image
+    }
+
+    fn fake_zimage(version: &str) -> Vec<u8> {
+        use flate2::{write::GzEncoder, Compression};
+        use std::io::Write;
+
+        let mut image = vec![0; 0x4000];
+        image[0x24..0x28].copy_from_slice(&[0x18, 0x28, 0x6F, 0x01]);
+
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&[0; 1024]).unwrap();
+        encoder.write_all(format!("Linux version {} (builder@host)", version).as_bytes()).unwrap();
+        image.extend(encoder.finish().unwrap());
+        image
+    }
+
+    fn fake_uboot(version: &str) -> Vec<u8> {
+        let mut image = vec![0; 8192];
+        image.extend(format!("U-Boot {} (Oct 01 2019 - 10:00:00 +0000)", version).as_bytes());
+        image.extend(&[0; 1024]);
+        image
+    }
+
+    #[test]
+    fn linux_kernel_versions() {
+        let version = "4.7.4-1-ARCH";
+        for image in &[
+            fake_uimage(&format!("Linux-{}", version)),
+            fake_bzimage(&format!("{} (builder@host) #1 SMP", version)),
+            fake_arm64_image(version),
+            fake_zimage(version),
+        ] {
+            assert_eq!(
+                linux_kernel_version(Cursor::new(image)).unwrap(),
+                Some(version.to_string())
+            );
+        }
+
+        assert_eq!(linux_kernel_version(Cursor::new(vec![0; 4096])).unwrap(), None);
+
+        // A target truncated right after the bzImage magic
+        let truncated = fake_bzimage(version)[..0x208].to_vec();
+        assert_eq!(linux_kernel_version(Cursor::new(truncated)).unwrap(), None);
+    }
+
+    #[test]
+    fn uboot_versions() {
+        assert_eq!(
+            uboot_version(Cursor::new(fake_uboot("2019.07"))).unwrap(),
+            Some("2019.07".to_string())
+        );
+        assert_eq!(uboot_version(Cursor::new(vec![0; 4096])).unwrap(), None);
+    }
+
+    #[test]
+    fn known_pattern_matches() {
+        let obj = fake_test_object(CONTENT_SHA256SUM);
+
+        let rule = known_pattern("2019.07", KnownPatternKind::UBoot);
+        assert!(!check(&rule, &obj, false, Cursor::new(fake_uboot("2019.07"))).unwrap());
+        assert!(check(&rule, &obj, false, Cursor::new(fake_uboot("2019.10"))).unwrap());
+
+        let rule = known_pattern("5.4.0", KnownPatternKind::LinuxKernel);
+        assert!(!check(&rule, &obj, false, Cursor::new(fake_zimage("5.4.0"))).unwrap());
+        assert!(check(&rule, &obj, false, Cursor::new(fake_uimage("Linux-4.19.0"))).unwrap());
+    }
+
+    fn custom_pattern(
+        version: &str,
+        regexp: &str,
+        seek: u64,
+        buffer_size: u64,
+    ) -> InstallIfDifferent {
+        InstallIfDifferent::CustomPattern {
+            version: version.to_string(),
+            pattern: Pattern { regexp: regexp.to_string(), seek, buffer_size },
+        }
+    }
+
+    #[test]
+    fn custom_pattern_matches() {
+        let obj = fake_test_object(CONTENT_SHA256SUM);
+        let mut firmware = vec![0; 2048];
+        firmware.extend(b"FW-VERSION:1.2.3;");
+        firmware.extend(&[0; 2048]);
+
+        let rule = custom_pattern("1.2.3", r"FW-VERSION:([0-9.]+);", 2000, 128);
+        assert!(!check(&rule, &obj, false, Cursor::new(&firmware)).unwrap());
+
+        let rule = custom_pattern("1.2.4", r"FW-VERSION:([0-9.]+);", 2000, 128);
+        assert!(check(&rule, &obj, false, Cursor::new(&firmware)).unwrap());
+
+        let rule = custom_pattern("1.2.3", r"[0-9]+\.[0-9]+\.[0-9]+", 2048, 32);
+        assert!(!check(&rule, &obj, false, Cursor::new(&firmware)).unwrap());
+
+        // Version is out of the read buffer
+        let rule = custom_pattern("1.2.3", r"FW-VERSION:([0-9.]+);", 0, 2048);
+        assert!(check(&rule, &obj, false, Cursor::new(&firmware)).unwrap());
+    }
+
+    #[test]
+    fn custom_pattern_is_relative_to_the_object() {
+        let obj = fake_test_object(CONTENT_SHA256SUM);
+        let mut target = Cursor::new(b"0.1 1.0".to_vec());
+        target.seek(SeekFrom::Start(4)).unwrap();
+
+        let rule = custom_pattern("1.0", r"[0-9.]+", 0, 3);
+        assert!(!check(&rule, &obj, false, target).unwrap());
+    }
+
+    #[test]
+    fn custom_pattern_invalid_regexp() {
+        let obj = fake_test_object(CONTENT_SHA256SUM);
+        let rule = custom_pattern("1.0", r"([0-9.]+", 0, 16);
+
+        assert!(check(&rule, &obj, false, Cursor::new(b"1.0")).is_err());
+    }
+
+    #[test]
+    fn checksum_of_compressed_object() {
+        let obj = fake_test_object(CONTENT_SHA256SUM);
+
+        assert!(check(&InstallIfDifferent::CheckSum, &obj, true, Cursor::new(CONTENT)).unwrap());
+    }
+}
@@ -0,0 +1,433 @@
+// Copyright (C) 2019 O.S. Systems Sofware LTDA
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::{
+    object::{
+        installer::{self, Context},
+        Info, Installer,
+    },
+    utils::{self, definitions::TargetTypeExt, fs::CompressKind},
+};
+use failure::{bail, ensure, format_err};
+use pkg_schema::objects;
+use serde::Deserialize;
+use slog_scope::info;
+use std::{
+    collections::HashMap,
+    fs,
+    io::{self, BufReader, Read},
+    path::Path,
+};
+
+const ARTIFACT_FORMAT: &str = "mender";
+const ARTIFACT_VERSION: u32 = 3;
+const ROOTFS_PAYLOAD: &str = "rootfs-image";
+
+#[derive(Deserialize)]
+struct Version {
+    format: String,
+    version: u32,
+}
+
+#[derive(Deserialize)]
+struct HeaderInfo {
+    payloads: Vec<Payload>,
+}
+
+#[derive(Deserialize)]
+struct Payload {
+    #[serde(rename = "type")]
+    kind: String,
+}
+
+impl Installer for objects::Mender {
+    fn check_requirements(&self, _: &Context) -> Result<(), failure::Error> {
+        info!("'mender' handle checking requirements");
+        if self.target.valid()?.is_block_device() {
+            return Ok(());
+        }
+
+        bail!("Unexpected target type, expected some device.")
+    }
+
+    fn install(&self, context: &Context) -> Result<(), failure::Error> {
+        info!("'mender' handler Install");
+
+        let target = self.target.get_target()?;
+        let source = context.download_dir.join(self.sha256sum());
+        let mut artifact = tar::Archive::new(BufReader::new(fs::File::open(source)?));
+        let mut manifest = None;
+        let mut checksums = HashMap::new();
+        let mut installed = false;
+
+        // The manifest precedes the payload in the artifact, so every
+        // entry is checked before anything is written into the target.
+        for entry in artifact.entries()? {
+            let mut entry = entry?;
+            let name = entry.path()?.to_string_lossy().into_owned();
+
+            match name.as_str() {
+                "version" => {
+                    let mut content = Vec::new();
+                    entry.read_to_end(&mut content)?;
+                    let version = serde_json::from_slice::<Version>(&content)?;
+                    ensure!(
+                        version.format == ARTIFACT_FORMAT && version.version == ARTIFACT_VERSION,
+                        "Unsupported artifact format: {} version {}",
+                        version.format,
+                        version.version
+                    );
+                    checksums.insert(name, installer::sha256sum(&content[..], &mut io::sink())?.0);
+                }
+                "manifest" => manifest = Some(parse_manifest(entry)?),
+                "manifest.sig" => info!("Artifact signature is not verified"),
+                n if n.starts_with("header.tar") => {
+                    let mut content = Vec::new();
+                    entry.read_to_end(&mut content)?;
+                    check_header(compress_kind(&name, "header.tar")?, &content[..])?;
+                    checksums.insert(name, installer::sha256sum(&content[..], &mut io::sink())?.0);
+                }
+                n if n.starts_with("data/0000.tar") => {
+                    let manifest = required_manifest(&manifest)?;
+                    check_checksums(manifest, &checksums)?;
+
+                    let kind = compress_kind(&name, "data/0000.tar")?;
+                    write_payload(kind, BufReader::new(entry), &target, manifest)?;
+                    installed = true;
+                }
+                n => bail!("Unexpected entry in artifact: {}", n),
+            }
+        }
+
+        ensure!(installed, "Artifact has no rootfs payload");
+        check_checksums(required_manifest(&manifest)?, &checksums)
+    }
+
+    fn verify(&self, context: &Context) -> Result<(), failure::Error> {
+        info!("'mender' handler Verify");
+
+        let target = self.target.get_target()?;
+        let source = context.download_dir.join(self.sha256sum());
+        let mut artifact = tar::Archive::new(BufReader::new(fs::File::open(source)?));
+        let mut manifest = None;
+
+        for entry in artifact.entries()? {
+            let entry = entry?;
+            let name = entry.path()?.to_string_lossy().into_owned();
+
+            match name.as_str() {
+                "manifest" => manifest = Some(parse_manifest(entry)?),
+                n if n.starts_with("data/0000.tar") => {
+                    let manifest = required_manifest(&manifest)?;
+                    let kind = compress_kind(&name, "data/0000.tar")?;
+                    let (name, len) = payload_image(kind, BufReader::new(entry))?;
+                    let expected = manifest_checksum(manifest, &name)?;
+
+                    let (found, found_len) = installer::sha256sum(
+                        fs::File::open(&target)?.take(len),
+                        &mut io::sink(),
+                    )?;
+                    if found_len != len || &found != expected {
+                        return Err(installer::Error::VerificationFailed { target }.into());
+                    }
+
+                    return Ok(());
+                }
+                _ => {}
+            }
+        }
+
+        bail!("Artifact has no rootfs payload")
+    }
+
+    fn plan(&self, _: &Context) -> Result<installer::Plan, failure::Error> {
+        Ok(installer::Plan {
+            target: Some(self.target.get_target()?),
+            write: self.size,
+            ..installer::Plan::default()
+        })
+    }
+}
+
+// Gets the compression used by the artifact entry `name` from its
+// extension after `prefix`.
+fn compress_kind(name: &str, prefix: &str) -> Result<Option<CompressKind>, failure::Error> {
+    match &name[prefix.len()..] {
+        "" => Ok(None),
+        ".gz" => Ok(Some(CompressKind::GZip)),
+        ".xz" => Ok(Some(CompressKind::Xz)),
+        ".zst" => Ok(Some(CompressKind::Zstd)),
+        ext => Err(format_err!("Unsupported compression for '{}': {}", name, ext)),
+    }
+}
+
+fn uncompressed<'a, R: io::BufRead + 'a>(
+    kind: Option<CompressKind>,
+    input: R,
+) -> io::Result<Box<dyn Read + 'a>> {
+    match kind {
+        Some(kind) => utils::io::decompressor(kind, input),
+        None => Ok(Box::new(input)),
+    }
+}
+
+fn parse_manifest<R: Read>(mut manifest: R) -> Result<HashMap<String, String>, failure::Error> {
+    let mut content = String::new();
+    manifest.read_to_string(&mut content)?;
+
+    content
+        .lines()
+        .map(|line| {
+            let mut fields = line.split_whitespace();
+            match (fields.next(), fields.next(), fields.next()) {
+                (Some(checksum), Some(name), None) => Ok((name.to_string(), checksum.to_string())),
+                _ => Err(format_err!("Invalid manifest line: {}", line)),
+            }
+        })
+        .collect()
+}
+
+fn required_manifest(
+    manifest: &Option<HashMap<String, String>>,
+) -> Result<&HashMap<String, String>, failure::Error> {
+    manifest.as_ref().ok_or_else(|| format_err!("Artifact manifest must precede its payload"))
+}
+
+fn manifest_checksum<'a>(
+    manifest: &'a HashMap<String, String>,
+    name: &str,
+) -> Result<&'a String, failure::Error> {
+    manifest.get(name).ok_or_else(|| format_err!("'{}' is not in the artifact manifest", name))
+}
+
+fn check_checksums(
+    manifest: &HashMap<String, String>,
+    checksums: &HashMap<String, String>,
+) -> Result<(), failure::Error> {
+    for (name, checksum) in checksums {
+        ensure!(
+            manifest_checksum(manifest, name)? == checksum,
+            "Checksum mismatch for '{}' in artifact",
+            name
+        );
+    }
+
+    Ok(())
+}
+
+fn check_header<R: io::BufRead>(
+    kind: Option<CompressKind>,
+    header: R,
+) -> Result<(), failure::Error> {
+    let mut header = tar::Archive::new(uncompressed(kind, header)?);
+
+    for entry in header.entries()? {
+        let entry = entry?;
+        if entry.path()?.as_os_str() == "header-info" {
+            let info = serde_json::from_reader::<_, HeaderInfo>(entry)?;
+            ensure!(
+                info.payloads.len() == 1 && info.payloads[0].kind == ROOTFS_PAYLOAD,
+                "Only artifacts with a single '{}' payload are supported",
+                ROOTFS_PAYLOAD
+            );
+            return Ok(());
+        }
+    }
+
+    bail!("Artifact header has no header-info")
+}
+
+// Writes the rootfs image inside the payload into `target`, failing
+// before it is opened if the image is not listed in `manifest`.
+fn write_payload<R: io::BufRead>(
+    kind: Option<CompressKind>,
+    payload: R,
+    target: &Path,
+    manifest: &HashMap<String, String>,
+) -> Result<(), failure::Error> {
+    let mut payload = tar::Archive::new(uncompressed(kind, payload)?);
+    let mut written = false;
+
+    for entry in payload.entries()? {
+        let entry = entry?;
+        let name = format!("data/0000/{}", entry.path()?.display());
+        ensure!(!written, "Rootfs payload has more than one image: {}", name);
+        let expected = manifest_checksum(manifest, &name)?;
+
+        info!("Writing '{}' into {:?}", name, target);
+        let mut output = fs::OpenOptions::new().write(true).open(target)?;
+        let (checksum, _) = installer::sha256sum(entry, &mut output)?;
+        ensure!(&checksum == expected, "Checksum mismatch for '{}' in artifact", name);
+        output.sync_all()?;
+        written = true;
+    }
+
+    ensure!(written, "Rootfs payload has no image");
+    Ok(())
+}
+
+// Gets the manifest name and length of the rootfs image inside the
+// payload.
+fn payload_image<R: io::BufRead>(
+    kind: Option<CompressKind>,
+    payload: R,
+) -> Result<(String, u64), failure::Error> {
+    let mut payload = tar::Archive::new(uncompressed(kind, payload)?);
+    let entry =
+        payload.entries()?.next().ok_or_else(|| format_err!("Rootfs payload has no image"))??;
+
+    Ok((format!("data/0000/{}", entry.path()?.display()), entry.header().size()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::object::installer::tests::context;
+    use flate2::{write::GzEncoder, Compression};
+    use pkg_schema::definitions;
+    use pretty_assertions::assert_eq;
+    use std::io::Write;
+    use tempfile::{tempdir, NamedTempFile};
+
+    const ROOTFS: &[u8] = b"rootfs image content";
+    const VERSION: &[u8] = br#"{"format":"mender","version":3}"#;
+
+    fn tarball(files: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut builder = tar::Builder::new(Vec::new());
+        for (path, data) in files {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(data.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder.append_data(&mut header, path, *data).unwrap();
+        }
+
+        builder.into_inner().unwrap()
+    }
+
+    fn gzip(data: &[u8]) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    fn checksum(data: &[u8]) -> String {
+        installer::sha256sum(data, &mut io::sink()).unwrap().0
+    }
+
+    // Builds an artifact whose manifest lists the rootfs image with
+    // `rootfs_checksum`, or leaves it out when `None`.
+    fn fake_artifact(version: &[u8], rootfs_checksum: Option<&str>) -> Vec<u8> {
+        let header = gzip(&tarball(&[
+            ("header-info", br#"{"payloads":[{"type":"rootfs-image"}]}"#),
+            ("headers/0000/type-info", br#"{"type":"rootfs-image"}"#),
+        ]));
+        let data = gzip(&tarball(&[("rootfs.ext4", ROOTFS)]));
+        let mut manifest =
+            format!("{}  header.tar.gz\n{}  version\n", checksum(&header), checksum(version));
+        if let Some(rootfs_checksum) = rootfs_checksum {
+            manifest += &format!("{}  data/0000/rootfs.ext4\n", rootfs_checksum);
+        }
+
+        tarball(&[
+            ("version", version),
+            ("manifest", manifest.as_bytes()),
+            ("header.tar.gz", &header),
+            ("data/0000.tar.gz", &data),
+        ])
+    }
+
+    fn fake_mender_obj(
+        artifact: &[u8],
+        download_dir: &Path,
+        target: &Path,
+    ) -> Result<objects::Mender, failure::Error> {
+        let obj = objects::Mender {
+            filename: "artifact.mender".to_string(),
+            size: artifact.len() as u64,
+            sha256sum: checksum(artifact),
+            target: definitions::TargetType::Device(target.to_path_buf()),
+        };
+        fs::write(download_dir.join(obj.sha256sum()), artifact)?;
+
+        Ok(obj)
+    }
+
+    // Installs `artifact`, returning the result together with the
+    // target content.
+    fn exec_install(artifact: &[u8]) -> (Result<(), failure::Error>, Vec<u8>) {
+        let download_dir = tempdir().unwrap();
+        let target = NamedTempFile::new().unwrap();
+        let obj = fake_mender_obj(artifact, download_dir.path(), target.path()).unwrap();
+
+        let res = obj
+            .check_requirements(&context(download_dir.path()))
+            .and_then(|_| obj.install(&context(download_dir.path())));
+
+        (res, fs::read(target.path()).unwrap())
+    }
+
+    #[test]
+    fn install_rootfs_payload() {
+        let (res, content) = exec_install(&fake_artifact(VERSION, Some(&checksum(ROOTFS))));
+
+        res.unwrap();
+        assert_eq!(content, ROOTFS);
+    }
+
+    #[test]
+    fn install_corrupted_payload() {
+        let (res, _) = exec_install(&fake_artifact(VERSION, Some(&checksum(b"other"))));
+
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn install_unsupported_version() {
+        let (res, content) = exec_install(&fake_artifact(
+            br#"{"format":"mender","version":2}"#,
+            Some(&checksum(ROOTFS)),
+        ));
+
+        assert!(res.is_err());
+        assert!(content.is_empty());
+    }
+
+    #[test]
+    fn install_payload_missing_from_manifest() {
+        let (res, content) = exec_install(&fake_artifact(VERSION, None));
+
+        assert!(res.is_err());
+        assert!(content.is_empty());
+    }
+
+    #[test]
+    fn install_manifest_after_payload() {
+        let data = gzip(&tarball(&[("rootfs.ext4", ROOTFS)]));
+        let manifest = format!("{}  data/0000/rootfs.ext4\n", checksum(ROOTFS));
+        let artifact = tarball(&[
+            ("version", VERSION),
+            ("data/0000.tar.gz", &data),
+            ("manifest", manifest.as_bytes()),
+        ]);
+        let (res, content) = exec_install(&artifact);
+
+        assert!(res.is_err());
+        assert!(content.is_empty());
+    }
+
+    #[test]
+    fn verify_rootfs_payload() {
+        let artifact = fake_artifact(VERSION, Some(&checksum(ROOTFS)));
+        let download_dir = tempdir().unwrap();
+        let target = NamedTempFile::new().unwrap();
+        let obj = fake_mender_obj(&artifact, download_dir.path(), target.path()).unwrap();
+
+        obj.install(&context(download_dir.path())).unwrap();
+        obj.verify(&context(download_dir.path())).unwrap();
+
+        fs::write(target.path(), b"corrupted image content").unwrap();
+        assert!(obj.verify(&context(download_dir.path())).is_err());
+    }
+}
@@ -3,70 +3,282 @@
 // SPDX-License-Identifier: Apache-2.0
 
 mod copy;
+mod external;
 mod flash;
 mod imxkobs;
+mod install_if_different;
+mod mender;
 mod raw;
+mod raw_delta;
+mod shell;
 mod tarball;
 mod test;
 mod ubifs;
+mod zephyr;
 
-use pkg_schema::Object;
+use crate::{firmware::installation_set, utils::definitions::TargetTypeExt};
+use crypto_hash::{Algorithm, Hasher};
+use failure::Fail;
+use pkg_schema::{definitions, Object};
+use serde::Serialize;
 use slog_scope::debug;
+use std::{
+    fmt,
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+};
+
+#[derive(Fail, Debug)]
+pub(crate) enum Error {
+    #[fail(
+        display = "Not enough space on {:?}: {} bytes are required but only {} are available",
+        target, required, available
+    )]
+    NotEnoughSpace { target: PathBuf, required: u64, available: u64 },
+
+    #[fail(display = "Verification failed, content of {:?} differs from the object", target)]
+    VerificationFailed { target: PathBuf },
+}
+
+/// Information about the installation in progress, besides the object
+/// itself, available to the installers.
+#[derive(Debug, Clone)]
+pub(crate) struct Context {
+    /// Directory holding the downloaded objects
+    pub(crate) download_dir: PathBuf,
+    /// Directory holding the firmware metadata and device specific
+    /// configuration
+    pub(crate) metadata_path: PathBuf,
+    /// Installation set receiving the update
+    pub(crate) installation_set: installation_set::Set,
+    /// UID of the package being installed
+    pub(crate) package_uid: String,
+    /// Position of the object in the package's objects list
+    pub(crate) object_index: usize,
+    /// Device written by the object at the same position on the active
+    /// installation set, which holds the content currently in use
+    pub(crate) active_target: Option<PathBuf>,
+}
+
+/// What installing an object would do to the device, as reported by
+/// dry-run installations.
+#[derive(Debug, Default, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct Plan {
+    /// Filename of the object
+    pub(crate) filename: String,
+    /// Whether the object would be installed, or skipped for already
+    /// matching the target accordingly to its install if different rule
+    pub(crate) install: bool,
+    /// Device, volume or directory receiving the object
+    pub(crate) target: Option<PathBuf>,
+    /// Filesystem the target would be formatted with
+    pub(crate) format: Option<String>,
+    /// Filesystem the target would be mounted as
+    pub(crate) mount: Option<String>,
+    /// Path, inside the mounted target, receiving the object
+    pub(crate) target_path: Option<PathBuf>,
+    /// Number of bytes which would be written
+    pub(crate) write: u64,
+    /// Program which would be run to install the object
+    pub(crate) run: Option<PathBuf>,
+}
+
+impl fmt::Display for Plan {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if !self.install {
+            return write!(f, "'{}' is already installed and would be skipped", self.filename);
+        }
+
+        write!(f, "'{}' would write {} bytes", self.filename, self.write)?;
+        if let Some(target) = &self.target {
+            write!(f, " to {:?}", target)?;
+        }
+        if let Some(target_path) = &self.target_path {
+            write!(f, " at {:?}", target_path)?;
+        }
+        if let Some(filesystem) = &self.format {
+            write!(f, ", formatting it as {}", filesystem)?;
+        }
+        if let Some(filesystem) = &self.mount {
+            write!(f, ", mounting it as {}", filesystem)?;
+        }
+        if let Some(program) = &self.run {
+            write!(f, ", running {:?}", program)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Returns the device written by `object`, when it writes into one.
+pub(crate) fn device_target(object: &Object) -> Option<PathBuf> {
+    let target = match object {
+        Object::Copy(o) => &o.target_type,
+        Object::Raw(o) => &o.target_type,
+        Object::RawDelta(o) => &o.target_type,
+        Object::Tarball(o) => &o.target,
+        _ => return None,
+    };
+    if !target.is_block_device() {
+        return None;
+    }
+
+    target.get_target().ok()
+}
+
+/// Ensures the `required` bytes fit in the `available` space of
+/// `target`.
+pub(crate) fn check_space(target: &Path, required: u64, available: u64) -> Result<(), Error> {
+    if required > available {
+        return Err(Error::NotEnoughSpace { target: target.to_path_buf(), required, available });
+    }
+
+    Ok(())
+}
+
+/// Ensures `target`, which is found at `path`, starts with the whole
+/// content of `source`.
+pub(crate) fn check_content<S: Read, T: Read>(
+    path: &Path,
+    source: S,
+    target: T,
+) -> Result<(), failure::Error> {
+    let (expected, len) = sha256sum(source, &mut io::sink())?;
+    let (found, found_len) = sha256sum(target.take(len), &mut io::sink())?;
+    if found_len != len || found != expected {
+        return Err(Error::VerificationFailed { target: path.to_path_buf() }.into());
+    }
+
+    Ok(())
+}
+
+/// Copies `input` into `output`, returning the checksum and length of
+/// the copied data.
+pub(crate) fn sha256sum<R: Read, W: Write>(
+    mut input: R,
+    output: &mut W,
+) -> io::Result<(String, u64)> {
+    let mut hasher = Hasher::new(Algorithm::SHA256);
+    let mut buf = vec![0; definitions::ChunkSize::default().0];
+    let mut len = 0;
+
+    loop {
+        let read = input.read(&mut buf)?;
+        if read == 0 {
+            break;
+        }
+
+        hasher.write_all(&buf[..read])?;
+        output.write_all(&buf[..read])?;
+        len += read as u64;
+    }
+
+    Ok((hex::encode(hasher.finish()), len))
+}
 
 pub(crate) trait Installer {
-    fn check_requirements(&self) -> Result<(), failure::Error> {
+    fn check_requirements(&self, _: &Context) -> Result<(), failure::Error> {
         debug!("running default check_requirements");
         Ok(())
     }
 
+    /// Checks the `install-if-different` rule of the object against
+    /// the target, returning whether it still needs to be installed.
+    fn should_install(&self) -> Result<bool, failure::Error> {
+        debug!("running default should_install");
+        Ok(true)
+    }
+
     fn setup(&mut self) -> Result<(), failure::Error> {
         debug!("running default setup");
         Ok(())
     }
 
-    fn cleanup(&mut self) -> Result<(), failure::Error> {
+    fn cleanup(&mut self, _: &Context) -> Result<(), failure::Error> {
         debug!("running default cleanup");
         Ok(())
     }
 
-    fn install(&self, download_dir: &std::path::Path) -> Result<(), failure::Error>;
+    fn install(&self, context: &Context) -> Result<(), failure::Error>;
+
+    /// Reads back what was installed on the target, failing if it does
+    /// not match the object.
+    fn verify(&self, _: &Context) -> Result<(), failure::Error> {
+        debug!("running default verify");
+        Ok(())
+    }
+
+    /// Describes what installing the object would do, without touching
+    /// the target.
+    fn plan(&self, _: &Context) -> Result<Plan, failure::Error> {
+        debug!("running default plan");
+        Ok(Plan::default())
+    }
 }
 
 impl Installer for Object {
-    fn check_requirements(&self) -> Result<(), failure::Error> {
-        for_any_object!(self, o, { o.check_requirements() })
+    fn check_requirements(&self, context: &Context) -> Result<(), failure::Error> {
+        for_any_object!(self, o, { o.check_requirements(context) })
+    }
+
+    fn should_install(&self) -> Result<bool, failure::Error> {
+        for_any_object!(self, o, { o.should_install() })
     }
 
     fn setup(&mut self) -> Result<(), failure::Error> {
         for_any_object!(self, o, { o.setup() })
     }
 
-    fn install(&self, download_dir: &std::path::Path) -> Result<(), failure::Error> {
-        for_any_object!(self, o, { o.install(download_dir) })
+    fn install(&self, context: &Context) -> Result<(), failure::Error> {
+        for_any_object!(self, o, { o.install(context) })
     }
 
-    fn cleanup(&mut self) -> Result<(), failure::Error> {
-        for_any_object!(self, o, { o.cleanup() })
+    fn verify(&self, context: &Context) -> Result<(), failure::Error> {
+        for_any_object!(self, o, { o.verify(context) })
+    }
+
+    fn cleanup(&mut self, context: &Context) -> Result<(), failure::Error> {
+        for_any_object!(self, o, { o.cleanup(context) })
+    }
+
+    fn plan(&self, context: &Context) -> Result<Plan, failure::Error> {
+        for_any_object!(self, o, { o.plan(context) })
     }
 }
 
 #[cfg(test)]
-mod tests {
+pub(crate) mod tests {
+    use super::{check_content, installation_set, Context};
+    use crate::{settings, utils::fs::CompressKind};
+    use failure::ensure;
     use lazy_static::lazy_static;
     use std::{
         env, fs,
-        io::Write,
+        io::{self, Write},
         os::unix::fs::PermissionsExt,
         path::{Path, PathBuf},
+        process,
         sync::{Arc, Mutex},
     };
-    use tempfile::TempDir;
+    use tempfile::{NamedTempFile, TempDir};
 
     // Used to serialize access to Loop devices across tests
     lazy_static! {
         pub static ref SERIALIZE: Arc<Mutex<()>> = Arc::new(Mutex::default());
     }
 
+    pub fn context(download_dir: &Path) -> Context {
+        Context {
+            download_dir: download_dir.to_path_buf(),
+            metadata_path: settings::Firmware::default().metadata_path,
+            installation_set: installation_set::Set::A,
+            package_uid: String::default(),
+            object_index: 0,
+            active_target: None,
+        }
+    }
+
     fn create_echo_bin(bin: &Path, output: &Path) -> Result<(), failure::Error> {
         let mut file = std::fs::File::create(bin)?;
         file.write_all(
@@ -102,4 +314,53 @@ mod tests {
 
         Ok((mocks, calls))
     }
+
+    pub fn compress_file(
+        source: &Path,
+        kind: CompressKind,
+    ) -> Result<NamedTempFile, failure::Error> {
+        let mut compressed = NamedTempFile::new_in(source.parent().unwrap_or(&env::temp_dir()))?;
+        let mut input = fs::File::open(source)?;
+        let output = compressed.as_file_mut();
+
+        match kind {
+            CompressKind::GZip => {
+                let mut encoder =
+                    flate2::write::GzEncoder::new(output, flate2::Compression::default());
+                io::copy(&mut input, &mut encoder)?;
+                encoder.finish()?;
+            }
+            CompressKind::BZip2 => {
+                let mut encoder = bzip2::write::BzEncoder::new(output, bzip2::Compression::Default);
+                io::copy(&mut input, &mut encoder)?;
+                encoder.finish()?;
+            }
+            CompressKind::Xz => {
+                let mut encoder = xz2::write::XzEncoder::new(output, 6);
+                io::copy(&mut input, &mut encoder)?;
+                encoder.finish()?;
+            }
+            CompressKind::Zstd => zstd::stream::copy_encode(&mut input, output, 0)?,
+            CompressKind::LZip => {
+                let status = process::Command::new("lzip")
+                    .arg("-c")
+                    .stdin(input)
+                    .stdout(output.try_clone()?)
+                    .status()?;
+                ensure!(status.success(), "lzip exited with error: {}", status);
+            }
+        }
+
+        Ok(compressed)
+    }
+
+    #[test]
+    fn check_content_of_target() {
+        let source = b"installed content";
+
+        check_content(Path::new("target"), &source[..], &b"installed content and more"[..])
+            .unwrap();
+        assert!(check_content(Path::new("target"), &source[..], &b"installed"[..]).is_err());
+        assert!(check_content(Path::new("target"), &source[..], &b"installed c0ntent"[..]).is_err());
+    }
 }
@@ -6,82 +6,256 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use crate::{
-    object::{Info, Installer},
-    utils::{self, definitions::TargetTypeExt},
+    object::{
+        installer::{self, Context},
+        Info, Installer,
+    },
+    utils::{self, definitions::TargetTypeExt, sparse::Extent},
 };
+use crypto_hash::{Algorithm, Hasher};
 use failure::bail;
 use pkg_schema::{definitions, objects};
 use slog_scope::info;
 use std::{
     fs,
-    io::{BufRead, Seek, SeekFrom, Write},
+    io::{self, BufRead, Read, Seek, SeekFrom, Write},
+    iter,
     path::Path,
 };
 
 impl Installer for objects::Raw {
-    fn check_requirements(&self) -> Result<(), failure::Error> {
+    fn check_requirements(&self, context: &Context) -> Result<(), failure::Error> {
         info!("'raw' handle checking requirements");
-        if self.compressed {
-            unimplemented!("FIXME: check the required_uncompressed_size");
-        }
+        if self.target_type.valid()?.is_block_device() {
+            let device = &self.target_type.get_target()?;
+            if let Some(device_size) = utils::fs::block_device_size(device)? {
+                let source = context.download_dir.join(self.sha256sum());
+                installer::check_space(
+                    device,
+                    written_size(self, &source)?,
+                    device_size.saturating_sub(self.seek * self.chunk_size.0 as u64),
+                )?;
+            }
 
-        if let definitions::TargetType::Device(_) = self.target_type.valid()? {
             return Ok(());
         }
 
         bail!("Unexpected target type, expected some device.")
     }
 
-    fn install(&self, download_dir: &Path) -> Result<(), failure::Error> {
+    fn should_install(&self) -> Result<bool, failure::Error> {
+        let rule = match self.install_if_different {
+            Some(ref rule) => rule,
+            None => return Ok(true),
+        };
+        let device = &self.target_type.get_target()?;
+
+        let mut target = fs::File::open(device)?;
+        target.seek(SeekFrom::Start(self.seek * self.chunk_size.0 as u64))?;
+        installer::install_if_different::check(rule, self, self.compressed, target)
+    }
+
+    fn install(&self, context: &Context) -> Result<(), failure::Error> {
         info!("'raw' handler Install");
 
-        let device = match self.target_type {
-            definitions::TargetType::Device(ref p) => p,
-            _ => unreachable!("Device should be secured by check_requirements"),
-        };
-        let source = download_dir.join(self.sha256sum());
+        let device = &self.target_type.get_target()?;
+        let source = context.download_dir.join(self.sha256sum());
         let chunk_size = self.chunk_size.0;
         let seek = self.seek * chunk_size as u64;
         let skip = self.skip.0 * chunk_size as u64;
         let truncate = self.truncate.0;
         let count = self.count.clone();
 
-        if self.compressed {
-            unimplemented!("FIXME: handle compressed installation");
+        let mut output = utils::io::timed_buf_writer(
+            chunk_size,
+            fs::OpenOptions::new().read(true).write(true).truncate(truncate).open(device)?,
+        );
+        output.seek(SeekFrom::Start(seek))?;
+
+        if sparse_image_size(self, &source)?.is_some() {
+            info!("Writing only the blocks holding data from the sparse image");
+            let mut target = fs::OpenOptions::new().read(true).write(true).open(device)?;
+            let mut written = 0;
+            let len = utils::sparse::walk(
+                source_reader(self, &source)?,
+                skip,
+                count_limit(self),
+                |offset, len, extent| {
+                    let mut data: Box<dyn Read> = match extent {
+                        Extent::Data(data) => Box::new(data),
+                        Extent::Fill(pattern) => Box::new(utils::sparse::fill_reader(pattern, len)),
+                    };
+                    if self.write_only_changed {
+                        target.seek(SeekFrom::Start(seek + offset))?;
+                        written += utils::io::copy_changed_chunks(
+                            chunk_size,
+                            iter::repeat(()),
+                            &mut data,
+                            &mut target,
+                        )?;
+                    } else {
+                        output.seek(SeekFrom::Start(seek + offset))?;
+                        written += io::copy(&mut data, &mut output)?;
+                    }
+                    Ok(())
+                },
+            )?;
+            output.flush()?;
+            info!("{} bytes written to {:?}", written, device);
+
+            // Holes at the end of the image are not written, so regular
+            // file targets must be extended to the image size.
+            let metadata = target.metadata()?;
+            if metadata.is_file() && metadata.len() < seek + len {
+                target.set_len(seek + len)?;
+            }
+
+            return Ok(());
+        }
+
+        let mut input: Box<dyn Read> = if self.compressed {
+            let mut input = utils::io::uncompressed_reader(&source)?;
+
+            // The uncompressed stream cannot be seeked so we discard the
+            // skipped bytes.
+            io::copy(&mut input.by_ref().take(skip), &mut io::sink())?;
+            input
         } else {
             let mut input = utils::io::timed_buf_reader(chunk_size, fs::File::open(source)?);
-
             input.seek(SeekFrom::Start(skip))?;
-            let mut output = utils::io::timed_buf_writer(
-                chunk_size,
-                fs::OpenOptions::new().read(true).write(true).truncate(truncate).open(device)?,
-            );
-            output.seek(SeekFrom::Start(seek))?;
-
-            for _ in count {
-                let buf = input.fill_buf()?;
-                let len = buf.len();
-
-                // We break the loop in case we have no bytes left for
-                // read (EOF is reached).
-                if len == 0 {
-                    break;
-                }
-
-                output.write_all(&buf)?;
-                input.consume(len);
-            }
+            Box::new(input)
+        };
+
+        if self.write_only_changed {
+            info!("Writing only the chunks which differ from the target content");
+            let mut target = fs::OpenOptions::new().read(true).write(true).open(device)?;
+            target.seek(SeekFrom::Start(seek))?;
+            let written =
+                utils::io::copy_changed_chunks(chunk_size, count, &mut input, &mut target)?;
+            info!("{} bytes written to {:?}", written, device);
+        } else {
+            utils::io::copy_chunks(chunk_size, count, &mut input, &mut output)?;
         }
+        output.flush()?;
 
         Ok(())
     }
+
+    fn verify(&self, context: &Context) -> Result<(), failure::Error> {
+        info!("'raw' handler Verify");
+
+        let device = &self.target_type.get_target()?;
+        let source = context.download_dir.join(self.sha256sum());
+        let chunk_size = self.chunk_size.0 as u64;
+        let skip = self.skip.0 * chunk_size;
+        let seek = self.seek * chunk_size;
+
+        if sparse_image_size(self, &source)?.is_some() {
+            let mut target = io::BufReader::new(fs::File::open(device)?);
+            utils::sparse::walk(
+                source_reader(self, &source)?,
+                skip,
+                count_limit(self),
+                |offset, len, extent| {
+                    let expected = match extent {
+                        Extent::Data(data) => installer::sha256sum(data, &mut io::sink())?.0,
+                        Extent::Fill(pattern) => {
+                            let mut hasher = Hasher::new(Algorithm::SHA256);
+                            utils::sparse::fill(pattern, len, &mut hasher)?;
+                            hex::encode(hasher.finish())
+                        }
+                    };
+                    target.seek(SeekFrom::Start(seek + offset))?;
+                    let (found, found_len) =
+                        installer::sha256sum((&mut target).take(len), &mut io::sink())?;
+                    if found_len != len || found != expected {
+                        return Err(installer::Error::VerificationFailed {
+                            target: device.clone(),
+                        }
+                        .into());
+                    }
+
+                    Ok(())
+                },
+            )?;
+
+            return Ok(());
+        }
+
+        let input: Box<dyn Read> = if self.compressed {
+            let mut input = utils::io::uncompressed_reader(&source)?;
+            io::copy(&mut input.by_ref().take(skip), &mut io::sink())?;
+            input
+        } else {
+            let mut input = io::BufReader::new(fs::File::open(source)?);
+            input.seek(SeekFrom::Start(skip))?;
+            Box::new(input)
+        };
+        let input = match self.count {
+            definitions::Count::Limited(n) => input.take(n as u64 * chunk_size),
+            definitions::Count::All => input.take(u64::MAX),
+        };
+
+        let mut target = io::BufReader::new(fs::File::open(device)?);
+        target.seek(SeekFrom::Start(seek))?;
+        installer::check_content(device, input, target)
+    }
+
+    fn plan(&self, context: &Context) -> Result<installer::Plan, failure::Error> {
+        let source = context.download_dir.join(self.sha256sum());
+        Ok(installer::Plan {
+            target: Some(self.target_type.get_target()?),
+            write: written_size(self, &source)?,
+            ..installer::Plan::default()
+        })
+    }
+}
+
+// Opens the uncompressed content of the `raw` object at `source`.
+fn source_reader(raw: &objects::Raw, source: &Path) -> Result<Box<dyn BufRead>, failure::Error> {
+    Ok(if raw.compressed {
+        Box::new(io::BufReader::new(utils::io::uncompressed_reader(source)?))
+    } else {
+        Box::new(io::BufReader::new(fs::File::open(source)?))
+    })
+}
+
+// Returns the expanded size of the `raw` object at `source` when it is
+// an Android sparse image.
+fn sparse_image_size(raw: &objects::Raw, source: &Path) -> Result<Option<u64>, failure::Error> {
+    Ok(utils::sparse::image_size(&mut source_reader(raw, source)?)?)
+}
+
+// Returns how many bytes of the `raw` object at `source` are written
+// to the target, after applying the skip and count rules.
+fn written_size(raw: &objects::Raw, source: &Path) -> Result<u64, failure::Error> {
+    let chunk_size = raw.chunk_size.0 as u64;
+    let source_size = match sparse_image_size(raw, source)? {
+        Some(size) => size,
+        None if raw.compressed => raw.required_uncompressed_size,
+        None => raw.size,
+    };
+    let size = source_size.saturating_sub(raw.skip.0 * chunk_size);
+
+    Ok(count_limit(raw).map_or(size, |limit| size.min(limit)))
+}
+
+fn count_limit(raw: &objects::Raw) -> Option<u64> {
+    match raw.count {
+        definitions::Count::Limited(n) => Some(n as u64 * raw.chunk_size.0 as u64),
+        definitions::Count::All => None,
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::{
+        object::installer::tests::{compress_file, context, SERIALIZE},
+        utils::fs::CompressKind,
+    };
     use pretty_assertions::assert_eq;
-    use std::{io, iter, path::PathBuf};
+    use std::{io::BufRead, iter, path::PathBuf};
     use tempfile::{tempdir, NamedTempFile};
 
     const DEFAULT_BYTE: u8 = 0xF;
@@ -120,6 +294,8 @@ mod tests {
                 seek,
                 count,
                 truncate: definitions::Truncate(truncate),
+                chunk_index: None,
+                write_only_changed: false,
             },
             download_dir.into_path(),
             source,
@@ -184,9 +360,10 @@ mod tests {
 
         let (mut obj, download_dir, mut source_guard, mut target_guard) =
             fake_raw_object(size, chunk_size, skip, seek, count.clone(), truncate).unwrap();
-        obj.check_requirements().unwrap();
+        obj.check_requirements(&context(&download_dir)).unwrap();
         obj.setup().unwrap();
-        obj.install(&download_dir).unwrap();
+        obj.install(&context(&download_dir)).unwrap();
+        obj.verify(&context(&download_dir)).unwrap();
 
         compare_files(
             source_guard.as_file_mut(),
@@ -210,9 +387,10 @@ mod tests {
 
         let (mut obj, download_dir, mut source_guard, mut target_guard) =
             fake_raw_object(size, chunk_size, skip, seek, count.clone(), truncate).unwrap();
-        obj.check_requirements().unwrap();
+        obj.check_requirements(&context(&download_dir)).unwrap();
         obj.setup().unwrap();
-        obj.install(&download_dir).unwrap();
+        obj.install(&context(&download_dir)).unwrap();
+        obj.verify(&context(&download_dir)).unwrap();
 
         compare_files(
             source_guard.as_file_mut(),
@@ -237,9 +415,10 @@ mod tests {
 
         let (mut obj, download_dir, mut source_guard, mut target_guard) =
             fake_raw_object(size, chunk_size, skip, seek, count.clone(), truncate).unwrap();
-        obj.check_requirements().unwrap();
+        obj.check_requirements(&context(&download_dir)).unwrap();
         obj.setup().unwrap();
-        obj.install(&download_dir).unwrap();
+        obj.install(&context(&download_dir)).unwrap();
+        obj.verify(&context(&download_dir)).unwrap();
 
         compare_files(
             source_guard.as_file_mut(),
@@ -264,9 +443,10 @@ mod tests {
 
         let (mut obj, download_dir, mut source_guard, mut target_guard) =
             fake_raw_object(size, chunk_size, skip, seek, count.clone(), truncate).unwrap();
-        obj.check_requirements().unwrap();
+        obj.check_requirements(&context(&download_dir)).unwrap();
         obj.setup().unwrap();
-        obj.install(&download_dir).unwrap();
+        obj.install(&context(&download_dir)).unwrap();
+        obj.verify(&context(&download_dir)).unwrap();
 
         compare_files(
             source_guard.as_file_mut(),
@@ -279,4 +459,255 @@ mod tests {
         .unwrap();
         check_unwritten_blocks(target_guard.as_file_mut(), 1024, 1024).unwrap();
     }
+
+    #[test]
+    fn raw_write_only_changed() {
+        let size = 2048;
+        let chunk_size = 128;
+        let count = definitions::Count::All;
+
+        let (mut obj, download_dir, mut source_guard, mut target_guard) =
+            fake_raw_object(size, chunk_size, 0, 0, count.clone(), false).unwrap();
+        obj.write_only_changed = true;
+        fs::copy(source_guard.path(), target_guard.path()).unwrap();
+        target_guard.as_file_mut().seek(SeekFrom::Start(1000)).unwrap();
+        target_guard.as_file_mut().write_all(&[DEFAULT_BYTE]).unwrap();
+
+        obj.check_requirements(&context(&download_dir)).unwrap();
+        obj.setup().unwrap();
+        obj.install(&context(&download_dir)).unwrap();
+        obj.verify(&context(&download_dir)).unwrap();
+
+        compare_files(
+            source_guard.as_file_mut(),
+            target_guard.as_file_mut(),
+            chunk_size,
+            0,
+            0,
+            count,
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn raw_verify_corrupted_target() {
+        let (mut obj, download_dir, _source_guard, mut target_guard) =
+            fake_raw_object(2048, 128, 0, 0, definitions::Count::All, false).unwrap();
+        obj.check_requirements(&context(&download_dir)).unwrap();
+        obj.setup().unwrap();
+        obj.install(&context(&download_dir)).unwrap();
+
+        target_guard.as_file_mut().seek(SeekFrom::Start(1000)).unwrap();
+        target_guard.as_file_mut().write_all(&[DEFAULT_BYTE]).unwrap();
+        assert!(obj.verify(&context(&download_dir)).is_err());
+    }
+
+    fn exec_compressed_copy(
+        kind: CompressKind,
+        chunk_size: usize,
+        skip: u64,
+        seek: u64,
+        count: definitions::Count,
+    ) {
+        let size = 2048;
+        let (mut obj, download_dir, mut source_guard, mut target_guard) =
+            fake_raw_object(size, chunk_size, skip, seek, count.clone(), false).unwrap();
+
+        // Use a non uniform content so misplaced chunks are detected
+        source_guard
+            .as_file_mut()
+            .write_all(&(0..size).map(|i| (i % 251) as u8).collect::<Vec<_>>())
+            .unwrap();
+        let compressed = compress_file(source_guard.path(), kind).unwrap();
+        obj.compressed = true;
+        obj.sha256sum = compressed.path().to_string_lossy().to_string();
+
+        obj.check_requirements(&context(&download_dir)).unwrap();
+        obj.setup().unwrap();
+        obj.install(&context(&download_dir)).unwrap();
+        obj.verify(&context(&download_dir)).unwrap();
+
+        compare_files(
+            source_guard.as_file_mut(),
+            target_guard.as_file_mut(),
+            chunk_size,
+            skip,
+            seek,
+            count,
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn raw_compressed_full_copy() {
+        for kind in &[CompressKind::GZip, CompressKind::BZip2, CompressKind::Xz, CompressKind::Zstd]
+        {
+            exec_compressed_copy(*kind, 8, 0, 0, definitions::Count::All);
+        }
+    }
+
+    #[test]
+    fn raw_compressed_partial_copy() {
+        exec_compressed_copy(CompressKind::GZip, 128, 2, 4, definitions::Count::Limited(4));
+    }
+
+    #[test]
+    fn raw_compressed_invalid_content() {
+        let (mut obj, download_dir, _source_guard, _target_guard) =
+            fake_raw_object(2048, 8, 0, 0, definitions::Count::All, false).unwrap();
+        obj.compressed = true;
+
+        assert!(obj.install(&context(&download_dir)).is_err());
+    }
+
+    // Installs a sparse image with data at [0, 2048) and [5120, 7168),
+    // using 1024 bytes chunks, over a target filled with DEFAULT_BYTE.
+    fn exec_sparse_copy(
+        kind: Option<CompressKind>,
+        skip: u64,
+        seek: u64,
+        count: definitions::Count,
+    ) -> Vec<u8> {
+        use crate::utils::sparse::tests::{sparse_image, Chunk, BLOCK_SIZE};
+
+        let (image, _) = sparse_image(&[
+            Chunk::Raw((0..2 * BLOCK_SIZE).map(|i| (i % 251) as u8).collect()),
+            Chunk::DontCare(3),
+            Chunk::Fill([1, 2, 3, 4], 2),
+            Chunk::DontCare(1),
+        ]);
+        let (mut obj, download_dir, source_guard, target_guard) =
+            fake_raw_object(image.len() as u64, BLOCK_SIZE, skip, seek, count, false).unwrap();
+        fs::write(source_guard.path(), &image).unwrap();
+        fs::write(target_guard.path(), [DEFAULT_BYTE; 8 * BLOCK_SIZE]).unwrap();
+        let _compressed = kind.map(|kind| {
+            let compressed = compress_file(source_guard.path(), kind).unwrap();
+            obj.compressed = true;
+            obj.sha256sum = compressed.path().to_string_lossy().to_string();
+            compressed
+        });
+
+        obj.check_requirements(&context(&download_dir)).unwrap();
+        obj.setup().unwrap();
+        obj.install(&context(&download_dir)).unwrap();
+        obj.verify(&context(&download_dir)).unwrap();
+
+        fs::read(target_guard.path()).unwrap()
+    }
+
+    #[test]
+    fn raw_sparse_full_copy() {
+        let data = (0..2048).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+        let mut expected = vec![DEFAULT_BYTE; 8192];
+        expected[..2048].copy_from_slice(&data);
+        expected[5120..7168].copy_from_slice(&[1, 2, 3, 4].repeat(512));
+
+        assert_eq!(exec_sparse_copy(None, 0, 0, definitions::Count::All), expected);
+        assert_eq!(
+            exec_sparse_copy(Some(CompressKind::GZip), 0, 0, definitions::Count::All),
+            expected
+        );
+    }
+
+    #[test]
+    fn raw_sparse_partial_copy() {
+        let data = (0..2048).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+        let mut expected = vec![DEFAULT_BYTE; 8192];
+        expected[2048..3072].copy_from_slice(&data[1024..]);
+        expected[6144..7168].copy_from_slice(&[1, 2, 3, 4].repeat(256));
+
+        assert_eq!(exec_sparse_copy(None, 1, 2, definitions::Count::Limited(5)), expected);
+    }
+
+    #[test]
+    fn raw_sparse_extends_file_target() {
+        let target = exec_sparse_copy(None, 0, 4, definitions::Count::All);
+
+        assert_eq!(target.len(), 12 * 1024);
+        assert_eq!(&target[9216..11264], &[1, 2, 3, 4].repeat(512)[..]);
+    }
+
+    #[test]
+    fn raw_sparse_write_only_changed() {
+        use crate::utils::sparse::tests::{sparse_image, Chunk, BLOCK_SIZE};
+
+        let (image, expanded) = sparse_image(&[
+            Chunk::Raw((0..2 * BLOCK_SIZE).map(|i| (i % 251) as u8).collect()),
+            Chunk::DontCare(1),
+            Chunk::Fill([1, 2, 3, 4], 2),
+        ]);
+        let (mut obj, download_dir, source_guard, target_guard) =
+            fake_raw_object(image.len() as u64, BLOCK_SIZE, 0, 0, definitions::Count::All, false)
+                .unwrap();
+        obj.write_only_changed = true;
+        fs::write(source_guard.path(), &image).unwrap();
+        let mut target = expanded.clone();
+        target[100] = DEFAULT_BYTE;
+        target[4000] = DEFAULT_BYTE;
+        fs::write(target_guard.path(), &target).unwrap();
+
+        obj.check_requirements(&context(&download_dir)).unwrap();
+        obj.setup().unwrap();
+        obj.install(&context(&download_dir)).unwrap();
+        obj.verify(&context(&download_dir)).unwrap();
+
+        assert_eq!(fs::read(target_guard.path()).unwrap(), expanded);
+    }
+
+    #[test]
+    fn raw_install_if_different_checksum() {
+        let (mut obj, _download_dir, source, target) =
+            fake_raw_object(2048, 8, 0, 0, definitions::Count::All, false).unwrap();
+        let mut hasher = crypto_hash::Hasher::new(crypto_hash::Algorithm::SHA256);
+        hasher.write_all(&fs::read(source.path()).unwrap()).unwrap();
+        obj.sha256sum = hex::encode(hasher.finish());
+        obj.install_if_different = Some(definitions::InstallIfDifferent::CheckSum);
+
+        assert!(obj.should_install().unwrap());
+
+        fs::copy(source.path(), target.path()).unwrap();
+        assert!(!obj.should_install().unwrap());
+    }
+
+    #[test]
+    #[ignore]
+    fn raw_larger_than_device() {
+        const DEVICE_SIZE: u64 = 1024 * 1024;
+
+        let image = NamedTempFile::new().unwrap();
+        image.as_file().set_len(DEVICE_SIZE).unwrap();
+
+        let (loopdev, device) = {
+            // Loop device next_free is not thread safe
+            let _mutex = SERIALIZE.lock().unwrap();
+            let loopdev = loopdev::LoopControl::open().unwrap().next_free().unwrap();
+            let device = loopdev.path().unwrap();
+            loopdev.attach_file(image.path()).unwrap();
+            (loopdev, device)
+        };
+
+        let (mut obj, download_dir, _source_guard, _target_guard) =
+            fake_raw_object(DEVICE_SIZE, 1024, 0, 0, definitions::Count::All, false).unwrap();
+        obj.target_type = definitions::TargetType::Device(device);
+        let fits = obj.check_requirements(&context(&download_dir));
+
+        obj.seek = 1;
+        let seek_overflows = obj.check_requirements(&context(&download_dir));
+
+        obj.count = definitions::Count::Limited(1000);
+        let count_fits = obj.check_requirements(&context(&download_dir));
+
+        obj.compressed = true;
+        obj.required_uncompressed_size = DEVICE_SIZE * 2;
+        obj.count = definitions::Count::All;
+        obj.seek = 0;
+        let uncompressed_overflows = obj.check_requirements(&context(&download_dir));
+
+        loopdev.detach().unwrap();
+
+        assert!(fits.is_ok());
+        assert!(seek_overflows.is_err());
+        assert!(count_fits.is_ok());
+        assert!(uncompressed_overflows.is_err());
+    }
 }
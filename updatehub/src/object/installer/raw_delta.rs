@@ -0,0 +1,380 @@
+// Copyright (C) 2019 O.S. Systems Sofware LTDA
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::{
+    object::{
+        installer::{self, Context},
+        Info, Installer,
+    },
+    utils::{self, definitions::TargetTypeExt},
+};
+use failure::{bail, ensure, format_err};
+use nix::{libc::c_void, sys::mman};
+use pkg_schema::objects;
+use slog_scope::info;
+use std::{
+    fs,
+    io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write},
+    ops::Deref,
+    os::unix::io::AsRawFd,
+    path::Path,
+    ptr, slice,
+};
+use zstd::stream::{
+    raw::{DParameter, InBuffer, Operation, OutBuffer},
+    zio,
+};
+
+// Deltas are created by `zstd --patch-from`, which requires a window as
+// large as the source image when decompressing.
+#[cfg(target_pointer_width = "64")]
+const WINDOW_LOG_MAX: u32 = 31;
+#[cfg(not(target_pointer_width = "64"))]
+const WINDOW_LOG_MAX: u32 = 30;
+
+impl Installer for objects::RawDelta {
+    fn check_requirements(&self, context: &Context) -> Result<(), failure::Error> {
+        info!("'raw-delta' handle checking requirements");
+        if self.target_type.valid()?.is_block_device() {
+            let device = &self.target_type.get_target()?;
+            ensure!(self.source.exists(), "Delta source {:?} does not exists", self.source);
+
+            // The delta is only valid against the image in use, so the
+            // source must be the device of the active installation set.
+            let active = context.active_target.as_ref().ok_or_else(|| {
+                format_err!("Delta source has no counterpart on the active installation set")
+            })?;
+            ensure!(
+                fs::canonicalize(&self.source)? == fs::canonicalize(active)?,
+                "Delta source {:?} is not the active installation set device {:?}",
+                self.source,
+                active
+            );
+            if let Some(device_size) = utils::fs::block_device_size(device)? {
+                installer::check_space(device, self.target_size, device_size)?;
+            }
+
+            return Ok(());
+        }
+
+        bail!("Unexpected target type, expected some device.")
+    }
+
+    fn install(&self, context: &Context) -> Result<(), failure::Error> {
+        info!("'raw-delta' handler Install");
+
+        let device = &self.target_type.get_target()?;
+        let patch = context.download_dir.join(self.sha256sum());
+
+        // The delta refers to the whole source image, which is used as
+        // the decompression prefix. It is mapped, rather than read, and
+        // referenced by the decoder without a copy, so only the pages in
+        // use are kept in memory.
+        let source = Mapping::open(&self.source, self.source_size)?;
+        let (checksum, len) = installer::sha256sum(&source[..], &mut io::sink())?;
+        ensure!(
+            len == self.source_size && checksum == self.source_sha256sum,
+            "Content of {:?} differs from the delta source",
+            self.source
+        );
+
+        let decoder = PrefixDecoder::new(&source)?;
+        let input = zio::Reader::new(BufReader::new(fs::File::open(patch)?), decoder);
+
+        let mut output = BufWriter::new(fs::OpenOptions::new().write(true).open(device)?);
+        let (checksum, len) = installer::sha256sum(input, &mut output)?;
+        output.flush()?;
+        ensure!(
+            len == self.target_size && checksum == self.target_sha256sum,
+            "Patched image differs from the expected one"
+        );
+
+        Ok(())
+    }
+
+    fn verify(&self, _: &Context) -> Result<(), failure::Error> {
+        info!("'raw-delta' handler Verify");
+
+        let device = &self.target_type.get_target()?;
+
+        let target = BufReader::new(fs::File::open(device)?).take(self.target_size);
+        let (checksum, len) = installer::sha256sum(target, &mut io::sink())?;
+        if len != self.target_size || checksum != self.target_sha256sum {
+            return Err(installer::Error::VerificationFailed { target: device.clone() }.into());
+        }
+
+        Ok(())
+    }
+
+    fn plan(&self, _: &Context) -> Result<installer::Plan, failure::Error> {
+        Ok(installer::Plan {
+            target: Some(self.target_type.get_target()?),
+            write: self.target_size,
+            ..installer::Plan::default()
+        })
+    }
+}
+
+// Decoder of frames compressed against `prefix`. Unlike a dictionary,
+// the prefix is referenced rather than copied by zstd, so it must outlive
+// the decoder.
+struct PrefixDecoder<'a> {
+    context: zstd_safe::DCtx<'a>,
+    prefix: &'a [u8],
+}
+
+impl<'a> PrefixDecoder<'a> {
+    fn new(prefix: &'a [u8]) -> io::Result<Self> {
+        let mut context = zstd_safe::create_dctx();
+        zstd_safe::dctx_set_parameter(&mut context, DParameter::WindowLogMax(WINDOW_LOG_MAX))
+            .map_err(zstd_error)?;
+
+        let mut decoder = PrefixDecoder { context, prefix };
+        decoder.reinit()?;
+        Ok(decoder)
+    }
+}
+
+impl Operation for PrefixDecoder<'_> {
+    fn run(&mut self, input: &mut InBuffer<'_>, output: &mut OutBuffer<'_>) -> io::Result<usize> {
+        zstd_safe::decompress_stream(&mut self.context, output, input).map_err(zstd_error)
+    }
+
+    // The prefix is only valid for a single frame, so it is referenced
+    // again whenever a new one starts.
+    fn reinit(&mut self) -> io::Result<()> {
+        zstd_safe::dctx_reset(&mut self.context, zstd_safe::ResetDirective::ZSTD_reset_session_only)
+            .map_err(zstd_error)?;
+        zstd_safe::dctx_ref_prefix(&mut self.context, self.prefix).map_err(zstd_error)?;
+        Ok(())
+    }
+
+    fn finish(&mut self, _: &mut OutBuffer<'_>, finished_frame: bool) -> io::Result<usize> {
+        if finished_frame {
+            return Ok(0);
+        }
+
+        Err(io::Error::new(io::ErrorKind::UnexpectedEof, "incomplete frame"))
+    }
+}
+
+fn zstd_error(code: usize) -> io::Error {
+    io::Error::other(zstd_safe::get_error_name(code))
+}
+
+// Read only memory mapping of the start of a file or device.
+struct Mapping {
+    addr: *mut c_void,
+    len: usize,
+}
+
+impl Mapping {
+    fn open(path: &Path, len: u64) -> Result<Self, failure::Error> {
+        let mut file = fs::File::open(path)?;
+
+        // Accessing pages beyond the end of the file is a fault, rather
+        // than an error, so the size is checked beforehand.
+        let size = file.seek(SeekFrom::End(0))?;
+        ensure!(size >= len, "Content of {:?} is smaller than the delta source", path);
+        if len == 0 {
+            return Ok(Mapping { addr: ptr::null_mut(), len: 0 });
+        }
+
+        let len = len as usize;
+        let addr = unsafe {
+            mman::mmap(
+                ptr::null_mut(),
+                len,
+                mman::ProtFlags::PROT_READ,
+                mman::MapFlags::MAP_PRIVATE,
+                file.as_raw_fd(),
+                0,
+            )?
+        };
+
+        Ok(Mapping { addr, len })
+    }
+}
+
+impl Deref for Mapping {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        if self.len == 0 {
+            return &[];
+        }
+
+        unsafe { slice::from_raw_parts(self.addr as *const u8, self.len) }
+    }
+}
+
+impl Drop for Mapping {
+    fn drop(&mut self) {
+        if self.len != 0 {
+            let _ = unsafe { mman::munmap(self.addr, self.len) };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::object::installer::tests::context;
+    use pkg_schema::definitions;
+    use pretty_assertions::assert_eq;
+    use tempfile::{tempdir, NamedTempFile};
+
+    const IMAGE_SIZE: usize = 64 * 1024;
+
+    fn checksum(data: &[u8]) -> String {
+        installer::sha256sum(data, &mut io::sink()).unwrap().0
+    }
+
+    // Compresses `new` against `old` as `zstd --patch-from --long` does.
+    // The content size is left out so the frame declares a window which
+    // covers the whole source.
+    fn encode_delta(old: &[u8], new: &[u8]) -> Vec<u8> {
+        let mut context = zstd_safe::create_cctx();
+        for param in &[
+            zstd_safe::CParameter::WindowLog(28),
+            zstd_safe::CParameter::EnableLongDistanceMatching(true),
+            zstd_safe::CParameter::ContentSizeFlag(false),
+        ] {
+            zstd_safe::cctx_set_parameter(&mut context, *param).unwrap();
+        }
+        zstd_safe::cctx_ref_prefix(&mut context, old).unwrap();
+
+        let mut patch = vec![0; zstd_safe::compress_bound(new.len())];
+        let len = zstd_safe::compress2(&mut context, &mut patch, new).unwrap();
+        patch.truncate(len);
+        patch
+    }
+
+    fn fake_delta_object(
+        download_dir: &Path,
+        old: &[u8],
+        new: &[u8],
+    ) -> (objects::RawDelta, NamedTempFile, NamedTempFile) {
+        let patch = encode_delta(old, new);
+        let patch_sha256sum = checksum(&patch);
+        fs::write(download_dir.join(&patch_sha256sum), &patch).unwrap();
+
+        let source = NamedTempFile::new().unwrap();
+        fs::write(source.path(), old).unwrap();
+        let target = NamedTempFile::new().unwrap();
+
+        let obj = objects::RawDelta {
+            filename: "rootfs.delta".to_string(),
+            size: patch.len() as u64,
+            sha256sum: patch_sha256sum,
+            target_type: definitions::TargetType::Device(target.path().to_path_buf()),
+            source: source.path().to_path_buf(),
+            source_size: old.len() as u64,
+            source_sha256sum: checksum(old),
+            target_size: new.len() as u64,
+            target_sha256sum: checksum(new),
+        };
+
+        (obj, source, target)
+    }
+
+    fn fake_images() -> (Vec<u8>, Vec<u8>) {
+        let old = (0..IMAGE_SIZE).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+        let mut new = old.clone();
+        new[1000..1100].iter_mut().for_each(|b| *b = 0xAA);
+        new.extend_from_slice(b"appended content");
+
+        (old, new)
+    }
+
+    // Gets the window log declared by the header of `frame`.
+    fn window_log(frame: &[u8]) -> u32 {
+        const SINGLE_SEGMENT: u8 = 0x20;
+
+        assert_eq!(frame[4] & SINGLE_SEGMENT, 0, "frame has no window descriptor");
+        10 + u32::from(frame[5] >> 3)
+    }
+
+    #[test]
+    fn install_delta() {
+        let download_dir = tempdir().unwrap();
+        let (old, new) = fake_images();
+        let (obj, source, target) = fake_delta_object(download_dir.path(), &old, &new);
+        let context = Context {
+            active_target: Some(source.path().to_path_buf()),
+            ..context(download_dir.path())
+        };
+
+        obj.check_requirements(&context).unwrap();
+        obj.install(&context).unwrap();
+        obj.verify(&context).unwrap();
+        assert_eq!(fs::read(target.path()).unwrap(), new);
+    }
+
+    #[test]
+    fn install_delta_larger_than_default_window() {
+        // zstd refuses windows over 128MiB unless told otherwise.
+        const DEFAULT_WINDOW_LOG_MAX: u32 = 27;
+
+        let (mut old, mut new) = fake_images();
+        old.resize((128 + 1) * 1024 * 1024, 0);
+        new.extend_from_slice(&old[old.len() - IMAGE_SIZE..]);
+
+        let download_dir = tempdir().unwrap();
+        let (obj, _source, target) = fake_delta_object(download_dir.path(), &old, &new);
+        let patch = fs::read(download_dir.path().join(&obj.sha256sum)).unwrap();
+        assert!(window_log(&patch) > DEFAULT_WINDOW_LOG_MAX);
+
+        obj.install(&context(download_dir.path())).unwrap();
+        assert_eq!(fs::read(target.path()).unwrap(), new);
+    }
+
+    #[test]
+    fn install_delta_over_different_source() {
+        let download_dir = tempdir().unwrap();
+        let (old, new) = fake_images();
+        let (obj, mut source, _target) = fake_delta_object(download_dir.path(), &old, &new);
+        source.seek(SeekFrom::Start(10)).unwrap();
+        source.write_all(&[0xFF]).unwrap();
+
+        assert!(obj.install(&context(download_dir.path())).is_err());
+    }
+
+    #[test]
+    fn install_delta_over_truncated_source() {
+        let download_dir = tempdir().unwrap();
+        let (old, new) = fake_images();
+        let (obj, source, _target) = fake_delta_object(download_dir.path(), &old, &new);
+        source.as_file().set_len(obj.source_size / 2).unwrap();
+
+        assert!(obj.install(&context(download_dir.path())).is_err());
+    }
+
+    #[test]
+    fn source_outside_active_installation_set() {
+        let download_dir = tempdir().unwrap();
+        let (old, new) = fake_images();
+        let (obj, _source, target) = fake_delta_object(download_dir.path(), &old, &new);
+
+        assert!(obj.check_requirements(&context(download_dir.path())).is_err());
+
+        let context = Context {
+            active_target: Some(target.path().to_path_buf()),
+            ..context(download_dir.path())
+        };
+        assert!(obj.check_requirements(&context).is_err());
+    }
+
+    #[test]
+    fn verify_corrupted_target() {
+        let download_dir = tempdir().unwrap();
+        let (old, new) = fake_images();
+        let (obj, _source, mut target) = fake_delta_object(download_dir.path(), &old, &new);
+        obj.install(&context(download_dir.path())).unwrap();
+        target.seek(SeekFrom::Start(10)).unwrap();
+        target.write_all(&[0xFF]).unwrap();
+
+        assert!(obj.verify(&context(download_dir.path())).is_err());
+    }
+}
@@ -0,0 +1,97 @@
+// Copyright (C) 2019 O.S. Systems Sofware LTDA
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::object::{
+    installer::{self, Context},
+    Info, Installer,
+};
+use failure::ensure;
+use pkg_schema::objects;
+use slog_scope::{error, info};
+use std::process::Command;
+
+const SHELL: &str = "/bin/sh";
+
+impl Installer for objects::Shell {
+    fn install(&self, context: &Context) -> Result<(), failure::Error> {
+        info!("'shell' handler Install");
+
+        let script = context.download_dir.join(self.sha256sum());
+        let output = Command::new(SHELL)
+            .arg(&script)
+            .current_dir(&context.download_dir)
+            .env("UPDATEHUB_DOWNLOAD_DIR", &context.download_dir)
+            .env("UPDATEHUB_INSTALLATION_SET", context.installation_set.to_string())
+            .env("UPDATEHUB_PACKAGE_UID", &context.package_uid)
+            .env("UPDATEHUB_OBJECT_INDEX", context.object_index.to_string())
+            .output()?;
+
+        String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .for_each(|line| info!("{} (stdout): {}", self.filename, line));
+        String::from_utf8_lossy(&output.stderr)
+            .lines()
+            .for_each(|line| error!("{} (stderr): {}", self.filename, line));
+        ensure!(output.status.success(), "{} exited with error: {}", self.filename, output.status);
+
+        Ok(())
+    }
+
+    fn plan(&self, context: &Context) -> Result<installer::Plan, failure::Error> {
+        Ok(installer::Plan {
+            run: Some(context.download_dir.join(self.sha256sum())),
+            ..installer::Plan::default()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{firmware::installation_set, object::installer::tests::context};
+    use pretty_assertions::assert_eq;
+    use std::fs;
+    use tempfile::tempdir;
+
+    fn fake_shell_obj(download_dir: &std::path::Path, script: &str) -> objects::Shell {
+        let obj = objects::Shell {
+            filename: "script.sh".to_string(),
+            size: script.len() as u64,
+            sha256sum: "e3b0c44298fc1c149afb".to_string(),
+        };
+        fs::write(download_dir.join(obj.sha256sum()), script).unwrap();
+
+        obj
+    }
+
+    #[test]
+    fn install_with_environment() {
+        let download_dir = tempdir().unwrap();
+        let obj = fake_shell_obj(
+            download_dir.path(),
+            "echo $UPDATEHUB_DOWNLOAD_DIR $UPDATEHUB_INSTALLATION_SET $UPDATEHUB_PACKAGE_UID \
+             $UPDATEHUB_OBJECT_INDEX > env\n",
+        );
+        let context = Context {
+            installation_set: installation_set::Set::B,
+            package_uid: "package-uid".to_string(),
+            object_index: 2,
+            ..context(download_dir.path())
+        };
+
+        obj.install(&context).unwrap();
+        assert_eq!(
+            fs::read_to_string(download_dir.path().join("env")).unwrap(),
+            format!("{} 1 package-uid 2\n", download_dir.path().display())
+        );
+    }
+
+    #[test]
+    fn install_failing_script() {
+        let download_dir = tempdir().unwrap();
+        let obj = fake_shell_obj(download_dir.path(), "echo failed >&2\nexit 3\n");
+
+        assert!(obj.install(&context(download_dir.path())).is_err());
+    }
+}
@@ -3,65 +3,217 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use crate::{
-    object::{Info, Installer},
-    utils::{self, definitions::TargetTypeExt},
+    object::{
+        installer::{self, Context},
+        Info, Installer,
+    },
+    utils::{
+        self,
+        definitions::TargetTypeExt,
+        fs::{MountMode, TarballKind},
+    },
 };
+use failure::{ensure, format_err};
 use pkg_schema::{definitions, objects};
 use slog_scope::info;
-use std::path::Path;
+use std::{
+    fs,
+    io::{BufReader, Read},
+    path::Path,
+};
 
 impl Installer for objects::Tarball {
-    fn check_requirements(&self) -> Result<(), failure::Error> {
+    fn check_requirements(&self, _: &Context) -> Result<(), failure::Error> {
         info!("'tarball' handle checking requirements");
-        if self.compressed {
-            unimplemented!("FIXME: check the required_uncompressed_size");
-        }
-
         match self.target {
             definitions::TargetType::Device(_)
+            | definitions::TargetType::PartLabel(_)
+            | definitions::TargetType::PartUUID(_)
+            | definitions::TargetType::FsUUID(_)
             | definitions::TargetType::UBIVolume(_)
-            | definitions::TargetType::MTDName(_) => self.target.valid().map(|_| ()),
+            | definitions::TargetType::MTDName(_) => {
+                self.target.valid()?;
+            }
+            definitions::TargetType::Path(_) => {
+                ensure!(!self.target_format.should_format, "Path targets cannot be formatted");
+                self.target.valid()?;
+            }
+        }
+
+        // A target which is going to be formatted has no filesystem to
+        // check the space of yet.
+        if self.target_format.should_format {
+            return Ok(());
         }
+
+        let target_path = self.target_path.strip_prefix("/").unwrap_or(&self.target_path);
+        utils::fs::target_map(
+            &self.target,
+            self.filesystem,
+            &self.mount_options,
+            MountMode::ReadOnly,
+            |path| {
+                let required =
+                    if self.compressed { self.required_uncompressed_size } else { self.size };
+                Ok(installer::check_space(
+                    &path.join(target_path),
+                    required,
+                    utils::fs::available_space(path)?,
+                )?)
+            },
+        )
     }
 
-    fn install(&self, download_dir: &Path) -> Result<(), failure::Error> {
+    fn install(&self, context: &Context) -> Result<(), failure::Error> {
         info!("'tarball' handler Install");
 
-        let device = self.target.get_target()?;
         let filesystem = self.filesystem;
         let mount_options = &self.mount_options;
         let format_options = &self.target_format.format_options;
         let sha256sum = self.sha256sum();
         let target_path = self.target_path.strip_prefix("/").unwrap_or(&self.target_path);
-        let source = download_dir.join(sha256sum);
+        let source = context.download_dir.join(sha256sum);
 
         if self.target_format.should_format {
-            utils::fs::format(&device, filesystem, format_options)?;
+            utils::fs::format(&self.target.get_target()?, filesystem, format_options)?;
         }
 
-        utils::fs::mount_map(&device, filesystem, mount_options, |path| {
-            let dest = path.join(target_path);
+        utils::fs::target_map(
+            &self.target,
+            filesystem,
+            mount_options,
+            MountMode::ReadWrite,
+            |path| {
+                let dest = path.join(target_path);
+                fs::create_dir_all(&dest)?;
+                unpack(archive_reader(&source)?, &dest, utils::fs::users_root(&self.target, path))
+            },
+        )
+    }
 
-            compress_tools::uncompress(
-                &source,
-                &dest,
-                utils::fs::find_compress_tarball_kind(&source)?,
-            )
+    fn verify(&self, context: &Context) -> Result<(), failure::Error> {
+        info!("'tarball' handler Verify");
+
+        let target_path = self.target_path.strip_prefix("/").unwrap_or(&self.target_path);
+        let source = context.download_dir.join(self.sha256sum());
+        let archive = archive_reader(&source)?;
+
+        utils::fs::target_map(
+            &self.target,
+            self.filesystem,
+            &self.mount_options,
+            MountMode::ReadOnly,
+            |path| {
+                let dest = path.join(target_path);
+
+                for entry in tar::Archive::new(archive).entries()? {
+                    let entry = entry?;
+                    let entry_path = entry.path()?;
+                    let installed = dest.join(entry_path.strip_prefix("/").unwrap_or(&entry_path));
+                    let metadata = installed
+                        .symlink_metadata()
+                        .map_err(|e| format_err!("Unable to verify {:?}: {}", installed, e))?;
+
+                    if entry.header().entry_type().is_file() {
+                        ensure!(
+                            metadata.len() == entry.header().size()?,
+                            "Size of {:?} differs from the archive",
+                            installed
+                        );
+                    }
+                }
+
+                Ok(())
+            },
+        )
+    }
+
+    fn plan(&self, _: &Context) -> Result<installer::Plan, failure::Error> {
+        let path_target = matches!(self.target, definitions::TargetType::Path(_));
+        Ok(installer::Plan {
+            target: Some(self.target.get_target()?),
+            format: Some(self.filesystem.to_string()).filter(|_| self.target_format.should_format),
+            mount: Some(self.filesystem.to_string()).filter(|_| !path_target),
+            target_path: Some(self.target_path.clone()),
+            write: if self.compressed { self.required_uncompressed_size } else { self.size },
+            ..installer::Plan::default()
         })
     }
 }
 
+// Opens `source` for reading its tar content.
+fn archive_reader(source: &Path) -> Result<Box<dyn Read>, failure::Error> {
+    Ok(match utils::fs::find_compress_tarball_kind(source)? {
+        TarballKind::Tar => Box::new(BufReader::new(fs::File::open(source)?)),
+        TarballKind::Compressed(_) => utils::io::uncompressed_reader(source)?,
+    })
+}
+
+// Extracts `archive` into `dest`, restoring the owner, permissions and
+// extended attributes of each entry. ACLs and SELinux labels, which GNU
+// tar stores apart from the other extended attributes, are restored as
+// well, looking named users and groups up on the filesystem mounted at
+// `root`.
+fn unpack<R: Read>(archive: R, dest: &Path, root: &Path) -> Result<(), failure::Error> {
+    let mut archive = tar::Archive::new(archive);
+    archive.set_preserve_permissions(true);
+    archive.set_preserve_ownerships(true);
+    archive.set_overwrite(true);
+
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let entry_path = entry.path()?.into_owned();
+        if !entry.unpack_in(dest)? {
+            info!("Skipping {:?} as it is outside of the target path", entry_path);
+            continue;
+        }
+
+        // Extended attributes are set once the owner is changed, as
+        // changing it drops the file capabilities.
+        let installed = dest.join(entry_path.strip_prefix("/").unwrap_or(&entry_path));
+        let extensions = match entry.pax_extensions()? {
+            Some(extensions) => extensions,
+            None => continue,
+        };
+        for extension in extensions {
+            let extension = extension?;
+            let key = extension.key()?;
+            let value = extension.value_bytes();
+
+            match key {
+                "RHT.security.selinux" => utils::xattr::set(&installed, "security.selinux", value)?,
+                "SCHILY.acl.access" => utils::xattr::set(
+                    &installed,
+                    "system.posix_acl_access",
+                    &utils::xattr::posix_acl_from_text(extension.value()?, root)?,
+                )?,
+                "SCHILY.acl.default" => utils::xattr::set(
+                    &installed,
+                    "system.posix_acl_default",
+                    &utils::xattr::posix_acl_from_text(extension.value()?, root)?,
+                )?,
+                _ => {
+                    if let Some(name) = key.strip_prefix("SCHILY.xattr.") {
+                        utils::xattr::set(&installed, name, value)?;
+                    }
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::object::installer::tests::SERIALIZE;
+    use crate::object::installer::tests::{context, SERIALIZE};
     use loopdev;
     use pretty_assertions::assert_eq;
     use std::{
-        fs,
-        io::{Seek, SeekFrom, Write},
+        io::{self, Seek, SeekFrom, Write},
         os::unix::fs::MetadataExt,
-        path::{Path, PathBuf},
+        path::PathBuf,
     };
     use tempfile;
 
@@ -107,32 +259,45 @@ mod tests {
         f(&mut obj);
 
         // Setup preinstall structure
-        utils::fs::mount_map(&device, definitions::Filesystem::Ext4, &"", |path| {
-            fs::create_dir(path.join("existing_dir"))?;
-            Ok(())
-        })?;
+        utils::fs::mount_map(
+            &device,
+            definitions::Filesystem::Ext4,
+            &"",
+            MountMode::ReadWrite,
+            |path| {
+                fs::create_dir(path.join("existing_dir"))?;
+                Ok(())
+            },
+        )?;
 
         // Peform Install
-        obj.check_requirements()?;
+        obj.check_requirements(&context(Path::new("test/fixtures")))?;
         obj.setup()?;
-        obj.install(&PathBuf::from("test/fixtures"))?;
+        obj.install(&context(Path::new("test/fixtures")))?;
+        obj.verify(&context(Path::new("test/fixtures")))?;
 
         // Validade File
-        utils::fs::mount_map(&device, obj.filesystem, &obj.mount_options.clone(), |path| {
-            let assert_metadata = |p: &Path| -> Result<(), failure::Error> {
-                let metadata = p.metadata()?;
-                assert_eq!(metadata.mode() % 0o1000, 0o664);
-                assert_eq!(metadata.uid(), 1000);
-                assert_eq!(metadata.gid(), 1000);
+        utils::fs::mount_map(
+            &device,
+            obj.filesystem,
+            &obj.mount_options.clone(),
+            MountMode::ReadOnly,
+            |path| {
+                let assert_metadata = |p: &Path| -> Result<(), failure::Error> {
+                    let metadata = p.metadata()?;
+                    assert_eq!(metadata.mode() % 0o1000, 0o664);
+                    assert_eq!(metadata.uid(), 1000);
+                    assert_eq!(metadata.gid(), 1000);
 
-                Ok(())
-            };
-            let dest = path.join(&obj.target_path.strip_prefix("/")?);
-            assert_metadata(&dest.join("tree/branch1/leaf"))?;
-            assert_metadata(&dest.join("tree/branch2/leaf"))?;
+                    Ok(())
+                };
+                let dest = path.join(&obj.target_path.strip_prefix("/")?);
+                assert_metadata(&dest.join("tree/branch1/leaf"))?;
+                assert_metadata(&dest.join("tree/branch2/leaf"))?;
 
-            Ok(())
-        })?;
+                Ok(())
+            },
+        )?;
 
         loopdev.detach()?;
 
@@ -150,4 +315,110 @@ mod tests {
     fn install_over_unformated_partion() {
         exec_test_with_tarball(|obj| obj.target_path = PathBuf::from("/existing_dir")).unwrap();
     }
+
+    fn fake_archive() -> Vec<u8> {
+        let mut builder = tar::Builder::new(Vec::new());
+
+        let mut header = tar::Header::new_gnu();
+        header.set_entry_type(tar::EntryType::Directory);
+        header.set_mode(0o750);
+        header.set_uid(1234);
+        header.set_gid(4321);
+        header.set_size(0);
+        builder.append_data(&mut header, "dir", io::empty()).unwrap();
+
+        builder
+            .append_pax_extensions(vec![("SCHILY.xattr.user.updatehub", &b"label"[..])])
+            .unwrap();
+        let mut header = tar::Header::new_gnu();
+        header.set_mode(0o640);
+        header.set_uid(1234);
+        header.set_gid(4321);
+        header.set_size(7);
+        builder.append_data(&mut header, "dir/file", &b"content"[..]).unwrap();
+
+        builder.into_inner().unwrap()
+    }
+
+    #[test]
+    fn install_acl_into_path() {
+        let download_dir = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+
+        let mut builder = tar::Builder::new(Vec::new());
+        builder
+            .append_pax_extensions(vec![(
+                "SCHILY.acl.access",
+                &b"user::rw-,user:root:r--,group::r--,mask::r--,other::---"[..],
+            )])
+            .unwrap();
+        let mut header = tar::Header::new_gnu();
+        header.set_mode(0o640);
+        header.set_uid(0);
+        header.set_gid(0);
+        header.set_size(7);
+        builder.append_data(&mut header, "file", &b"content"[..]).unwrap();
+        let archive = builder.into_inner().unwrap();
+        let source = download_dir.path().join("archive");
+        fs::write(&source, &archive).unwrap();
+
+        let obj = objects::Tarball {
+            filename: "".to_string(),
+            filesystem: definitions::Filesystem::Ext4,
+            size: archive.len() as u64,
+            sha256sum: source.to_string_lossy().to_string(),
+            target: definitions::TargetType::Path(target.path().to_path_buf()),
+            target_path: PathBuf::from("/app"),
+            compressed: false,
+            required_uncompressed_size: 0,
+            target_format: definitions::TargetFormat::default(),
+            mount_options: String::default(),
+        };
+
+        obj.check_requirements(&context(download_dir.path())).unwrap();
+        obj.install(&context(download_dir.path())).unwrap();
+        assert_eq!(fs::read(target.path().join("app/file")).unwrap(), b"content");
+    }
+
+    #[test]
+    fn unpack_with_metadata() {
+        let dest = tempfile::tempdir().unwrap();
+        unpack(&fake_archive()[..], dest.path(), Path::new("/")).unwrap();
+
+        let dir = dest.path().join("dir").metadata().unwrap();
+        assert_eq!(dir.mode() % 0o1000, 0o750);
+        assert_eq!((dir.uid(), dir.gid()), (1234, 4321));
+
+        let file = dest.path().join("dir/file");
+        let metadata = file.metadata().unwrap();
+        assert_eq!(fs::read(&file).unwrap(), b"content");
+        assert_eq!(metadata.mode() % 0o1000, 0o640);
+        assert_eq!((metadata.uid(), metadata.gid()), (1234, 4321));
+
+        let mut value = vec![0; 16];
+        let name = std::ffi::CString::new("user.updatehub").unwrap();
+        let path = std::ffi::CString::new(file.to_str().unwrap()).unwrap();
+        let len = unsafe {
+            nix::libc::getxattr(
+                path.as_ptr(),
+                name.as_ptr(),
+                value.as_mut_ptr() as *mut nix::libc::c_void,
+                value.len(),
+            )
+        };
+        assert_eq!(&value[..len as usize], b"label");
+    }
+
+    #[test]
+    fn read_zstd_archive() {
+        let mut source = tempfile::NamedTempFile::new().unwrap();
+        source.write_all(&zstd::encode_all(&fake_archive()[..], 0).unwrap()).unwrap();
+
+        let entries = tar::Archive::new(archive_reader(source.path()).unwrap())
+            .entries()
+            .unwrap()
+            .map(|e| e.unwrap().path().unwrap().into_owned())
+            .collect::<Vec<_>>();
+        assert_eq!(entries, vec![PathBuf::from("dir"), PathBuf::from("dir/file")]);
+    }
 }
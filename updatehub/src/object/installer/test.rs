@@ -2,11 +2,22 @@
 //
 // SPDX-License-Identifier: Apache-2.0
 
-use crate::object::Installer;
+use crate::object::{
+    installer::{self, Context},
+    Installer,
+};
 use pkg_schema::objects;
 
 impl Installer for objects::Test {
-    fn install(&self, _: &std::path::Path) -> Result<(), failure::Error> {
+    fn install(&self, _: &Context) -> Result<(), failure::Error> {
         Ok(())
     }
+
+    fn plan(&self, _: &Context) -> Result<installer::Plan, failure::Error> {
+        Ok(installer::Plan {
+            target: Some(self.target.clone().into()),
+            write: self.size,
+            ..installer::Plan::default()
+        })
+    }
 }
@@ -3,43 +3,86 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use crate::{
-    object::{Info, Installer},
+    object::{
+        installer::{self, Context},
+        Info, Installer,
+    },
     utils::{self, definitions::TargetTypeExt},
 };
-use failure::bail;
+use failure::{bail, ensure};
 use pkg_schema::{definitions, objects};
 use slog_scope::info;
+use std::{
+    fs,
+    io::{BufReader, Read},
+};
 
 impl Installer for objects::Ubifs {
-    fn check_requirements(&self) -> Result<(), failure::Error> {
+    fn check_requirements(&self, _: &Context) -> Result<(), failure::Error> {
         info!("'ubifs' handle checking requirements");
-        if self.compressed {
-            unimplemented!("FIXME: check the required_uncompressed_size");
-        }
-
-        utils::fs::is_executable_in_path("ubiupdatevol")?;
-        utils::fs::is_executable_in_path("ubinfo")?;
+        ensure!(
+            !self.compressed || self.required_uncompressed_size > 0,
+            "Compressed ubifs objects must provide the required uncompressed size"
+        );
 
         if let definitions::TargetType::UBIVolume(_) = self.target.valid()? {
+            let target = self.target.get_target()?;
+            installer::check_space(
+                &target,
+                volume_size(self),
+                utils::mtd::ubi_volume_size(&target)?,
+            )?;
+
             return Ok(());
         }
 
         bail!("Unexpected target type, expected some device.")
     }
 
-    fn install(&self, download_dir: &std::path::Path) -> Result<(), failure::Error> {
+    fn install(&self, context: &Context) -> Result<(), failure::Error> {
         info!("'ubifs' handler Install");
 
         let target = self.target.get_target()?;
-        let source = download_dir.join(self.sha256sum());
+        let source = context.download_dir.join(self.sha256sum());
+        let data: Box<dyn Read> = if self.compressed {
+            utils::io::uncompressed_reader(&source)?
+        } else {
+            Box::new(fs::File::open(&source)?)
+        };
 
-        if self.compressed {
-            unimplemented!("FIXME: handle compressed installation");
+        utils::mtd::update_ubi_volume(&target, volume_size(self), data)
+    }
+
+    fn verify(&self, context: &Context) -> Result<(), failure::Error> {
+        info!("'ubifs' handler Verify");
+
+        let target = self.target.get_target()?;
+        let source = context.download_dir.join(self.sha256sum());
+        let data: Box<dyn Read> = if self.compressed {
+            utils::io::uncompressed_reader(&source)?
         } else {
-            easy_process::run(&format!("ubiupdatevol {} {}", target.display(), source.display()))?;
-        }
+            Box::new(BufReader::new(fs::File::open(&source)?))
+        };
+
+        installer::check_content(&target, data, BufReader::new(fs::File::open(&target)?))
+    }
+
+    fn plan(&self, _: &Context) -> Result<installer::Plan, failure::Error> {
+        Ok(installer::Plan {
+            target: Some(self.target.get_target()?),
+            write: volume_size(self),
+            ..installer::Plan::default()
+        })
+    }
+}
 
-        Ok(())
+// The volume update size must be announced upfront, so compressed
+// objects rely on the required uncompressed size for it.
+fn volume_size(obj: &objects::Ubifs) -> u64 {
+    if obj.compressed {
+        obj.required_uncompressed_size
+    } else {
+        obj.size
     }
 }
 
@@ -47,11 +90,16 @@ impl Installer for objects::Ubifs {
 mod tests {
     use super::*;
     use crate::{
-        object::installer::tests::create_echo_bins,
-        utils::mtd::tests::{FakeUbi, MtdKind, SERIALIZE},
+        object::installer::tests::{compress_file, context},
+        utils::{
+            fs::CompressKind,
+            mtd::tests::{FakeUbi, MtdKind, SERIALIZE},
+        },
     };
     use pretty_assertions::assert_eq;
-    use std::env;
+    use std::path::Path;
+
+    const CONTENT_SIZE: usize = 4096;
 
     fn fake_ubifs_obj(name: &str) -> objects::Ubifs {
         objects::Ubifs {
@@ -65,38 +113,74 @@ mod tests {
         }
     }
 
-    #[test]
-    fn check_requirements_with_missing_binaries() {
-        let ubifs_obj = fake_ubifs_obj("home");
-
-        env::set_var("PATH", "");
-        assert!(ubifs_obj.check_requirements().is_err());
-
-        env::set_var("PATH", "");
-        let (_handle, _) = create_echo_bins(&["ubinfo"]).unwrap();
-        assert!(ubifs_obj.check_requirements().is_err());
+    fn exec_install(kind: Option<CompressKind>) {
+        let _mtd_lock = SERIALIZE.lock();
+        let _ubi = FakeUbi::new(&["home"], MtdKind::Nor).unwrap();
+        let download_dir = tempfile::tempdir().unwrap();
+        let content = (0..CONTENT_SIZE).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+        let source = download_dir.path().join("source");
+        fs::write(&source, &content).unwrap();
+
+        let mut ubifs_obj = fake_ubifs_obj("home");
+        ubifs_obj.size = CONTENT_SIZE as u64;
+        ubifs_obj.sha256sum = "source".to_string();
+        let _compressed = kind.map(|kind| {
+            let compressed = compress_file(&source, kind).unwrap();
+            ubifs_obj.sha256sum = compressed.path().to_string_lossy().to_string();
+            ubifs_obj.compressed = true;
+            ubifs_obj.required_uncompressed_size = CONTENT_SIZE as u64;
+            compressed
+        });
+
+        ubifs_obj.check_requirements(&context(download_dir.path())).unwrap();
+        ubifs_obj.install(&context(download_dir.path())).unwrap();
+        ubifs_obj.verify(&context(download_dir.path())).unwrap();
 
-        env::set_var("PATH", "");
-        let (_handle, _) = create_echo_bins(&["ubiupdatevol"]).unwrap();
-        assert!(ubifs_obj.check_requirements().is_err());
+        let target = ubifs_obj.target.get_target().unwrap();
+        assert_eq!(&fs::read(target).unwrap()[..CONTENT_SIZE], &content[..]);
     }
 
     #[test]
     #[ignore]
     fn install() {
+        exec_install(None);
+    }
+
+    #[test]
+    #[ignore]
+    fn check_requirements_larger_than_volume() {
         let _mtd_lock = SERIALIZE.lock();
         let _ubi = FakeUbi::new(&["home"], MtdKind::Nor).unwrap();
-        let ubifs_obj = fake_ubifs_obj("home");
-        let download_dir = tempfile::tempdir().unwrap();
-        let target = ubifs_obj.target.get_target().unwrap();
-        let source = download_dir.path().join(&ubifs_obj.sha256sum);
+        let mut ubifs_obj = fake_ubifs_obj("home");
+        ubifs_obj.size = 2 * 1024 * 1024;
 
-        let (_handle, calls) = create_echo_bins(&["ubiupdatevol"]).unwrap();
+        assert!(ubifs_obj.check_requirements(&context(Path::new("/"))).is_err());
+    }
 
-        ubifs_obj.check_requirements().unwrap();
-        ubifs_obj.install(download_dir.path()).unwrap();
+    #[test]
+    fn check_requirements_compressed_without_size() {
+        let mut ubifs_obj = fake_ubifs_obj("home");
+        ubifs_obj.compressed = true;
+        ubifs_obj.required_uncompressed_size = 0;
+
+        assert!(ubifs_obj.check_requirements(&context(Path::new("/"))).is_err());
+    }
 
-        let expected = format!("ubiupdatevol {} {}\n", target.display(), source.display());
-        assert_eq!(std::fs::read_to_string(calls).unwrap(), expected);
+    #[test]
+    #[ignore]
+    fn install_compressed_gzip() {
+        exec_install(Some(CompressKind::GZip));
+    }
+
+    #[test]
+    #[ignore]
+    fn install_compressed_xz() {
+        exec_install(Some(CompressKind::Xz));
+    }
+
+    #[test]
+    #[ignore]
+    fn install_compressed_bzip2() {
+        exec_install(Some(CompressKind::BZip2));
     }
 }
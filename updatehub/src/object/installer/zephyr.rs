@@ -0,0 +1,147 @@
+// Copyright (C) 2019 O.S. Systems Sofware LTDA
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::{
+    object::{
+        installer::{self, Context},
+        Info, Installer,
+    },
+    utils::{self, definitions::TargetTypeExt},
+};
+use failure::{bail, ensure};
+use pkg_schema::{definitions, objects};
+use slog_scope::{error, info};
+use std::{fs, process::Command};
+
+// Executable, in the metadata path, used to transfer the image to the
+// microcontroller when the device needs a custom backend.
+const TRANSFER_HELPER: &str = "zephyr-transfer";
+
+impl Installer for objects::Zephyr {
+    fn check_requirements(&self, _: &Context) -> Result<(), failure::Error> {
+        info!("'zephyr' handle checking requirements");
+        if let definitions::TargetType::Device(_) = self.target.valid()? {
+            return Ok(());
+        }
+
+        bail!("Unexpected target type, expected some device.")
+    }
+
+    fn install(&self, context: &Context) -> Result<(), failure::Error> {
+        info!("'zephyr' handler Install");
+
+        let target = self.target.get_target()?;
+        let source = context.download_dir.join(self.sha256sum());
+        let helper = context.metadata_path.join(TRANSFER_HELPER);
+
+        if helper.exists() {
+            info!("Transferring '{}' to {:?} using {:?}", self.filename, target, helper);
+            let output = Command::new(&helper).arg(&source).arg(&target).output()?;
+            String::from_utf8_lossy(&output.stdout)
+                .lines()
+                .for_each(|line| info!("{} (stdout): {}", TRANSFER_HELPER, line));
+            String::from_utf8_lossy(&output.stderr)
+                .lines()
+                .for_each(|line| error!("{} (stderr): {}", TRANSFER_HELPER, line));
+            ensure!(
+                output.status.success(),
+                "{} exited with error: {}",
+                TRANSFER_HELPER,
+                output.status
+            );
+        } else {
+            info!("Transferring '{}' to {:?} using mcumgr", self.filename, target);
+            let mut client = utils::mcumgr::Client::open(&target)?;
+            client.upload_image(&fs::read(source)?)?;
+            client.test_image()?;
+            client.reset()?;
+        }
+
+        info!("Image '{}' successfully transferred to {:?}", self.filename, target);
+        Ok(())
+    }
+
+    fn plan(&self, _: &Context) -> Result<installer::Plan, failure::Error> {
+        Ok(installer::Plan {
+            target: Some(self.target.get_target()?),
+            write: self.size,
+            ..installer::Plan::default()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{object::installer::tests::context, utils::mcumgr::tests::fake_device};
+    use pretty_assertions::assert_eq;
+    use std::{
+        io::Write,
+        os::unix::{fs::PermissionsExt, io::FromRawFd},
+        path::{Path, PathBuf},
+        thread,
+    };
+    use tempfile::tempdir;
+
+    fn fake_zephyr_obj(target: &Path) -> objects::Zephyr {
+        objects::Zephyr {
+            filename: "zephyr.bin".to_string(),
+            size: 1024,
+            sha256sum: "e3b0c44298fc1c149afb".to_string(),
+            target: definitions::TargetType::Device(target.to_path_buf()),
+        }
+    }
+
+    #[test]
+    fn install_using_mcumgr() {
+        let pty = nix::pty::openpty(None, None).unwrap();
+        let master = unsafe { fs::File::from_raw_fd(pty.master) };
+        let slave = unsafe { fs::File::from_raw_fd(pty.slave) };
+        let target = fs::read_link(format!("/proc/self/fd/{}", pty.slave)).unwrap();
+
+        let download_dir = tempdir().unwrap();
+        let metadata_path = tempdir().unwrap();
+        let obj = fake_zephyr_obj(&target);
+        let image = (0..obj.size).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+        fs::write(download_dir.path().join(obj.sha256sum()), &image).unwrap();
+
+        // The master is kept open until the install is done as closing it
+        // hangs up the terminal before the last response is read.
+        let (reader, writer) = (master.try_clone().unwrap(), master.try_clone().unwrap());
+        let device = thread::spawn(move || fake_device(reader, writer));
+
+        obj.check_requirements(&context(download_dir.path())).unwrap();
+        obj.install(&Context {
+            metadata_path: metadata_path.path().to_path_buf(),
+            ..context(download_dir.path())
+        })
+        .unwrap();
+        assert_eq!(device.join().unwrap(), image);
+        drop((master, slave));
+    }
+
+    #[test]
+    fn install_using_transfer_helper() {
+        let download_dir = tempdir().unwrap();
+        let metadata_path = tempdir().unwrap();
+        let calls = metadata_path.path().join("calls");
+        let helper = metadata_path.path().join(TRANSFER_HELPER);
+        let mut file = fs::File::create(&helper).unwrap();
+        writeln!(file, "#!/bin/sh\necho $@ > {:?}", calls).unwrap();
+        file.set_permissions(fs::Permissions::from_mode(0o755)).unwrap();
+        drop(file);
+
+        let obj = fake_zephyr_obj(&PathBuf::from("/dev/ttyACM0"));
+        obj.install(&Context {
+            metadata_path: metadata_path.path().to_path_buf(),
+            ..context(download_dir.path())
+        })
+        .unwrap();
+
+        assert_eq!(
+            fs::read_to_string(calls).unwrap(),
+            format!("{} /dev/ttyACM0\n", download_dir.path().join(obj.sha256sum()).display())
+        );
+    }
+}
@@ -54,12 +54,17 @@ macro_rules! for_any_object {
     ($mode:ident, $alias:ident, $code:block) => {
         match $mode {
             Object::Copy($alias) => $code,
+            Object::External($alias) => $code,
             Object::Flash($alias) => $code,
             Object::Imxkobs($alias) => $code,
+            Object::Mender($alias) => $code,
             Object::Raw($alias) => $code,
+            Object::RawDelta($alias) => $code,
+            Object::Shell($alias) => $code,
             Object::Tarball($alias) => $code,
             Object::Test($alias) => $code,
             Object::Ubifs($alias) => $code,
+            Object::Zephyr($alias) => $code,
         }
     };
 }
@@ -5,6 +5,7 @@
 #[macro_use]
 mod macros;
 
+pub(crate) mod chunks;
 pub(crate) mod info;
 pub(crate) mod installer;
 
@@ -141,19 +141,47 @@ impl Default for Storage {
 #[serde(rename_all = "PascalCase")]
 pub struct Update {
     pub download_dir: PathBuf,
+    /// Install modes accepted on update packages, which are rejected
+    /// when any of its objects uses another mode. Modes handled by
+    /// external install handlers must be listed as well. By default,
+    /// all modes built into the agent, but `shell` and `test`, are
+    /// accepted. As `shell` runs scripts supplied by the server as root,
+    /// it must be explicitly enabled by listing it, as in:
+    ///
+    /// ```ini
+    /// [Update]
+    /// SupportedInstallModes=copy,flash,imxkobs,mender,raw,raw-delta,shell,tarball,ubifs,zephyr
+    /// ```
     #[serde(rename = "SupportedInstallModes")]
     #[serde(deserialize_with = "de::vec_from_str")]
     pub install_modes: Vec<String>,
+    /// Defines if installations only check the objects requirements and
+    /// report what would be done, leaving the device untouched. By
+    /// default, it is disabled.
+    #[serde(deserialize_with = "de::bool_from_str")]
+    #[serde(default)]
+    pub dry_run: bool,
 }
 
 impl Default for Update {
     fn default() -> Self {
         Self {
             download_dir: "/tmp/updatehub".into(),
-            install_modes: ["dry-run", "copy", "flash", "imxkobs", "raw", "tarball", "ubifs"]
-                .iter()
-                .map(|i| (*i).to_string())
-                .collect(),
+            install_modes: [
+                "copy",
+                "flash",
+                "imxkobs",
+                "mender",
+                "raw",
+                "raw-delta",
+                "tarball",
+                "ubifs",
+                "zephyr",
+            ]
+            .iter()
+            .map(|i| (*i).to_string())
+            .collect(),
+            dry_run: false,
         }
     }
 }
@@ -212,6 +240,7 @@ RuntimeSettingsPath=/run/updatehub/state
 [Update]
 DownloadDir=/tmp/download
 SupportedInstallModes=mode1,mode2
+DryRun=true
 
 [Network]
 ServerAddress=http://localhost
@@ -223,6 +252,7 @@ ServerAddress=http://localhost
             update: Update {
                 download_dir: "/tmp/download".into(),
                 install_modes: ["mode1", "mode2"].iter().map(|i| i.to_string()).collect(),
+                dry_run: true,
             },
             network: Network {
                 server_address: "http://localhost".into(),
@@ -263,6 +293,7 @@ ListenSocket=localhost:8313
             update: Update {
                 download_dir: "/tmp/download".into(),
                 install_modes: ["mode1", "mode2"].iter().map(|i| i.to_string()).collect(),
+                dry_run: false,
             },
             network: Network {
                 server_address: "http://localhost".into(),
@@ -340,10 +371,21 @@ MetadataPath=/tmp/metadata
             },
             update: Update {
                 download_dir: "/tmp/updatehub".into(),
-                install_modes: ["dry-run", "copy", "flash", "imxkobs", "raw", "tarball", "ubifs"]
-                    .iter()
-                    .map(|i| i.to_string())
-                    .collect(),
+                install_modes: [
+                    "copy",
+                    "flash",
+                    "imxkobs",
+                    "mender",
+                    "raw",
+                    "raw-delta",
+                    "tarball",
+                    "ubifs",
+                    "zephyr",
+                ]
+                .iter()
+                .map(|i| i.to_string())
+                .collect(),
+                dry_run: false,
             },
             network: Network {
                 server_address: "https://api.updatehub.io".to_string(),
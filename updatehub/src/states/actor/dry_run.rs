@@ -0,0 +1,27 @@
+// Copyright (C) 2019 O.S. Systems Sofware LTDA
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use super::DryRun;
+use actix::{Context, Handler, Message, MessageResult};
+
+pub(crate) struct Request;
+pub(crate) enum Response {
+    Plan(DryRun),
+    NotFound,
+}
+
+impl Message for Request {
+    type Result = Response;
+}
+
+impl Handler<Request> for super::Machine {
+    type Result = MessageResult<Request>;
+
+    fn handle(&mut self, _: Request, _: &mut Context<Self>) -> Self::Result {
+        MessageResult(match &self.shared_state.dry_run {
+            Some(dry_run) => Response::Plan(dry_run.clone()),
+            None => Response::NotFound,
+        })
+    }
+}
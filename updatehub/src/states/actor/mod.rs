@@ -6,12 +6,15 @@
 mod test;
 
 pub(crate) mod download_abort;
+pub(crate) mod dry_run;
 pub(crate) mod info;
 pub(crate) mod probe;
 /// Used to send `Step` messages to the `Machine` actor.
 pub(crate) mod stepper;
 
-use super::{Idle, Metadata, Probe, RuntimeSettings, Settings, State, StateMachine};
+use super::{
+    install::DryRun, Idle, Metadata, Probe, RuntimeSettings, Settings, State, StateMachine,
+};
 use actix::{Actor, Addr, Arbiter, AsyncContext, Context, Handler, Message, MessageResult};
 use slog_scope::info;
 
@@ -26,6 +29,8 @@ pub(super) struct SharedState {
     pub(super) settings: Settings,
     pub(super) runtime_settings: RuntimeSettings,
     pub(super) firmware: Metadata,
+    /// Outcome of the last dry-run installation
+    pub(super) dry_run: Option<DryRun>,
 }
 
 impl SharedState {
@@ -57,7 +62,7 @@ impl Machine {
     ) -> Self {
         Machine {
             state: Some(state),
-            shared_state: SharedState { settings, runtime_settings, firmware },
+            shared_state: SharedState { settings, runtime_settings, firmware, dry_run: None },
             stepper: stepper::Controller::default(),
         }
     }
@@ -67,6 +67,7 @@ fn setup_actor(kind: Setup, probe: Probe) -> (Addr<Machine>, mockito::Mock, Sett
         Probe::Enabled => true,
         Probe::Disabled => false,
     };
+    settings.update.install_modes.push("test".to_string());
     let runtime_settings = RuntimeSettings::new().load(tmpfile.to_str().unwrap()).unwrap();
     let firmware = Metadata::from_path(&create_fake_metadata(match kind {
         Setup::HasUpdate => FakeDevice::HasUpdate,
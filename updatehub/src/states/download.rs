@@ -119,7 +119,7 @@ mod test {
 
         (
             State(PrepareDownload { update_package: get_update_package_with_shasum(shasum) }),
-            SharedState { settings, runtime_settings, firmware },
+            SharedState { settings, runtime_settings, firmware, dry_run: None },
         )
     }
 
@@ -214,6 +214,86 @@ mod test {
         );
     }
 
+    #[test]
+    fn download_chunked_object() {
+        use crate::{object::chunks::tests::split, update_package::UpdatePackage};
+        use serde_json::json;
+
+        let old = (0..10_000).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+        let mut new = old.clone();
+        new[5000] = 0xFF;
+        let shasum = hex_digest(Algorithm::SHA256, &new);
+        let (index, chunks) = split(&new, 1024);
+        let index_shasum = hex_digest(Algorithm::SHA256, &index);
+
+        let (mut predownload_state, mut shared_state) = fake_download_state(&shasum);
+        let seed = shared_state.settings.update.download_dir.with_extension("seed");
+        std::fs::write(&seed, &old).unwrap();
+        let raw_object = |target: &std::path::Path| {
+            json!({
+                "mode": "raw",
+                "filename": "rootfs.img",
+                "target-type": "device",
+                "target": target,
+                "sha256sum": shasum,
+                "size": new.len(),
+                "chunk-index": index_shasum,
+            })
+        };
+        predownload_state.0.update_package = UpdatePackage::parse(
+            &json!({
+                "product": "0123456789",
+                "version": "1.0",
+                "supported-hardware": ["board"],
+                "objects": [[raw_object(&seed)], [raw_object(&seed.with_extension("b"))]],
+            })
+            .to_string(),
+        )
+        .unwrap();
+
+        let route = |kind: &str, shasum: &str| {
+            format!(
+                "/products/{}/packages/{}/{}/{}",
+                "229ffd7e08721d716163fc81a2dbaf6c90d449f0a3b009b6a2defe8a0b0d7381",
+                &predownload_state.0.update_package.package_uid(),
+                kind,
+                shasum
+            )
+        };
+        let mut mocks = vec![mock("GET", route("objects", &index_shasum).as_str())
+            .with_status(200)
+            .with_body(&index)
+            .create()];
+        for (i, (chunk_shasum, chunk)) in chunks.iter().enumerate() {
+            // Only the changed chunk is missing from the active set
+            mocks.push(
+                mock("GET", route("chunks", chunk_shasum).as_str())
+                    .with_status(200)
+                    .with_body(chunk)
+                    .expect(if i == 4 { 1 } else { 0 })
+                    .create(),
+            );
+        }
+
+        let mut machine = StateMachine::PrepareDownload(predownload_state)
+            .move_to_next_state(&mut shared_state)
+            .unwrap()
+            .0;
+        loop {
+            machine = machine.move_to_next_state(&mut shared_state).unwrap().0;
+            if let StateMachine::Install(_) = machine {
+                break;
+            }
+        }
+
+        mocks.iter().for_each(|m| m.assert());
+        assert_eq!(
+            std::fs::read(shared_state.settings.update.download_dir.join(&shasum)).unwrap(),
+            new
+        );
+        std::fs::remove_file(seed).unwrap();
+    }
+
     #[test]
     fn download_small_object() {
         test_object_download(16)
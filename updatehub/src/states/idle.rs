@@ -54,7 +54,7 @@ fn polling_disable() {
     settings.polling.enabled = false;
     let runtime_settings = RuntimeSettings::default();
     let firmware = Metadata::from_path(&create_fake_metadata(FakeDevice::NoUpdate)).unwrap();
-    let mut shared_state = SharedState { settings, runtime_settings, firmware };
+    let mut shared_state = SharedState { settings, runtime_settings, firmware, dry_run: None };
 
     let machine =
         StateMachine::Idle(State(Idle {})).move_to_next_state(&mut shared_state).unwrap().0;
@@ -71,7 +71,7 @@ fn polling_enabled() {
     settings.polling.enabled = true;
     let runtime_settings = RuntimeSettings::default();
     let firmware = Metadata::from_path(&create_fake_metadata(FakeDevice::NoUpdate)).unwrap();
-    let mut shared_state = SharedState { settings, runtime_settings, firmware };
+    let mut shared_state = SharedState { settings, runtime_settings, firmware, dry_run: None };
 
     let machine =
         StateMachine::Idle(State(Idle {})).move_to_next_state(&mut shared_state).unwrap().0;
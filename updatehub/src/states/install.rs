@@ -8,9 +8,10 @@ use super::{
 };
 use crate::{
     firmware::installation_set,
-    object::{self, Installer},
+    object::{self, Info, Installer},
     update_package::UpdatePackage,
 };
+use serde::Serialize;
 use slog_scope::{debug, info};
 
 #[derive(Debug, PartialEq)]
@@ -18,6 +19,16 @@ pub(super) struct Install {
     pub(super) update_package: UpdatePackage,
 }
 
+/// Outcome of a dry-run installation, describing what installing each
+/// object of the package would do.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct DryRun {
+    pub(crate) package_uid: String,
+    pub(crate) installation_set: String,
+    pub(crate) objects: Vec<object::installer::Plan>,
+}
+
 create_state_step!(Install => Idle);
 create_state_step!(Install => Reboot(update_package));
 
@@ -71,17 +82,76 @@ impl StateChangeImpl for State<Install> {
         let installation_set = installation_set::inactive()?;
         info!("Using installation set as target {}", installation_set);
 
-        // FIXME: What is missing:
-        //
-        // - verify if the object needs to be installed, accordingly to the install if
-        //   different rule.
+        let context = object::installer::Context {
+            download_dir: shared_state.settings.update.download_dir.clone(),
+            metadata_path: shared_state.settings.firmware.metadata_path.clone(),
+            installation_set,
+            package_uid: package_uid.clone(),
+            object_index: 0,
+            active_target: None,
+        };
+        let active_targets = self
+            .0
+            .update_package
+            .objects(installation_set::active()?)
+            .iter()
+            .map(object::installer::device_target)
+            .collect::<Vec<_>>();
+        let object_context = |i| object::installer::Context {
+            object_index: i,
+            active_target: active_targets.get(i).cloned().flatten(),
+            ..context.clone()
+        };
 
         let objs = self.0.update_package.objects_mut(installation_set);
-        objs.iter().try_for_each(object::Installer::check_requirements)?;
-        objs.iter_mut().try_for_each(object::Installer::setup)?;
-        objs.iter_mut().try_for_each(|obj| {
-            obj.install(&shared_state.settings.update.download_dir)?;
-            obj.cleanup()
+        objs.iter()
+            .enumerate()
+            .try_for_each(|(i, obj)| obj.check_requirements(&object_context(i)))?;
+
+        // On dry-run the targets are only resolved and inspected, so the
+        // device and the active installation set are left untouched.
+        if shared_state.settings.update.dry_run {
+            let objects = objs
+                .iter()
+                .enumerate()
+                .map(|(i, obj)| {
+                    Ok(object::installer::Plan {
+                        filename: obj.filename().to_owned(),
+                        install: obj.should_install()?,
+                        ..obj.plan(&object_context(i))?
+                    })
+                })
+                .collect::<Result<Vec<_>, failure::Error>>()?;
+            objects.iter().for_each(|plan| info!("Dry-run: {}", plan));
+
+            shared_state.dry_run = Some(DryRun {
+                package_uid,
+                installation_set: installation_set.to_string(),
+                objects,
+            });
+            info!("Dry-run finished, no changes were made to the device");
+            return Ok((StateMachine::Idle(self.into()), actor::StepTransition::Immediate));
+        }
+
+        // Objects already matching the target contents, accordingly to
+        // the install if different rule, are skipped.
+        let mut objs = objs.iter_mut().enumerate().try_fold(Vec::new(), |mut objs, (i, obj)| {
+            if obj.should_install()? {
+                objs.push((i, obj));
+            } else {
+                info!("Skipping installation of '{}' as it is already installed", obj.filename());
+            }
+            Ok::<_, failure::Error>(objs)
+        })?;
+        objs.iter_mut().try_for_each(|(_, obj)| obj.setup())?;
+
+        // Each object is read back once installed so a corrupted write
+        // fails the update before the installation set is swapped.
+        objs.iter_mut().try_for_each(|(i, obj)| {
+            let context = object_context(*i);
+            obj.install(&context)?;
+            obj.verify(&context)?;
+            obj.cleanup(&context)
         })?;
 
         // Ensure we do a probe as soon as possible so full update
@@ -132,7 +202,7 @@ mod test {
 
         let runtime_settings = RuntimeSettings::default();
         let firmware = Metadata::from_path(&create_fake_metadata(FakeDevice::NoUpdate)).unwrap();
-        let shared_state = SharedState { settings, runtime_settings, firmware };
+        let shared_state = SharedState { settings, runtime_settings, firmware, dry_run: None };
 
         (State(Install { update_package: get_update_package() }), shared_state)
     }
@@ -151,6 +221,100 @@ mod test {
         }
     }
 
+    #[test]
+    fn dry_run_leaves_device_untouched() {
+        let (state, mut shared_state) = fake_install_state();
+        shared_state.settings.update.dry_run = true;
+        let machine = StateMachine::Install(state).move_to_next_state(&mut shared_state).unwrap().0;
+
+        match machine {
+            StateMachine::Idle(_) => {
+                assert_eq!(shared_state.runtime_settings.applied_package_uid(), None);
+                assert_eq!(shared_state.runtime_settings.is_polling_forced(), false);
+                assert_eq!(
+                    shared_state.dry_run,
+                    Some(DryRun {
+                        package_uid: get_update_package().package_uid(),
+                        installation_set: "1".to_string(),
+                        objects: vec![object::installer::Plan {
+                            filename: "testfile".to_string(),
+                            install: true,
+                            target: Some("/dev/device2".into()),
+                            write: 10,
+                            ..object::installer::Plan::default()
+                        }],
+                    })
+                );
+            }
+            s => panic!("Invalid success: {:?}", s),
+        }
+    }
+
+    #[test]
+    #[ignore]
+    fn dry_run_leaves_loop_device_untouched() {
+        use crate::{
+            object::installer::tests::SERIALIZE,
+            update_package::tests::create_fake_object,
+            utils::{self, fs::MountMode},
+        };
+        use pkg_schema::definitions::Filesystem;
+        use serde_json::json;
+
+        const CONTENT: &[u8] = b"new content";
+
+        let image = NamedTempFile::new().unwrap();
+        image.as_file().set_len(8 * 1024 * 1024).unwrap();
+        let (loopdev, device) = {
+            // Loop device next_free is not thread safe
+            let _mutex = SERIALIZE.lock().unwrap();
+            let loopdev = loopdev::LoopControl::open().unwrap().next_free().unwrap();
+            let device = loopdev.path().unwrap();
+            loopdev.attach_file(image.path()).unwrap();
+            (loopdev, device)
+        };
+        utils::fs::format(&device, Filesystem::Ext4, &None).unwrap();
+        utils::fs::mount_map(&device, Filesystem::Ext4, "", MountMode::ReadWrite, |path| {
+            Ok(fs::write(path.join("file"), b"old content")?)
+        })
+        .unwrap();
+
+        let (_, mut shared_state) = fake_install_state();
+        shared_state.settings.update.dry_run = true;
+        let sha256sum = crypto_hash::hex_digest(crypto_hash::Algorithm::SHA256, CONTENT);
+        create_fake_object(CONTENT, &sha256sum, &shared_state.settings);
+        let object = json!({
+            "mode": "copy",
+            "filename": "file",
+            "filesystem": "ext4",
+            "target-type": "device",
+            "target": device,
+            "target-path": "/file",
+            "install-if-different": "sha256sum",
+            "sha256sum": sha256sum,
+            "size": CONTENT.len()
+        });
+        let package = json!({
+            "product": "0123456789",
+            "version": "1.0",
+            "supported-hardware": ["board"],
+            "objects": [[object], [object]]
+        });
+        let update_package = UpdatePackage::parse(&package.to_string()).unwrap();
+
+        let before = fs::read(image.path()).unwrap();
+        let res = StateMachine::Install(State(Install { update_package }))
+            .move_to_next_state(&mut shared_state);
+        let after = fs::read(image.path()).unwrap();
+        loopdev.detach().unwrap();
+
+        match res.unwrap().0 {
+            StateMachine::Idle(_) => assert!(shared_state.dry_run.unwrap().objects[0].install),
+            s => panic!("Invalid success: {:?}", s),
+        }
+        assert!(before == after, "Dry-run has changed the device content");
+    }
+
     #[test]
     fn polling_now_if_succeed() {
         let (state, mut shared_state) = fake_install_state();
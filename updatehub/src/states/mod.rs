@@ -148,6 +148,11 @@ impl StateMachine {
             StateMachine::Probe(s) => s.handle(shared_state),
             StateMachine::PrepareDownload(s) => s.handle(shared_state),
             StateMachine::Download(s) => s.handle_with_callback_and_report_progress(shared_state),
+            // Dry-run installations leave the device untouched, so the
+            // server is not told about an installation that never happens.
+            StateMachine::Install(s) if shared_state.settings.update.dry_run => {
+                s.handle(shared_state)
+            }
             StateMachine::Install(s) => s.handle_with_callback_and_report_progress(shared_state),
             StateMachine::Reboot(s) => s.handle_with_callback_and_report_progress(shared_state),
         }
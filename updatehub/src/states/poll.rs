@@ -79,7 +79,7 @@ fn extra_poll_in_past() {
     runtime_settings.set_polling_extra_interval(Duration::seconds(20)).unwrap();
 
     let firmware = Metadata::from_path(&create_fake_metadata(FakeDevice::NoUpdate)).unwrap();
-    let mut shared_state = SharedState { settings, runtime_settings, firmware };
+    let mut shared_state = SharedState { settings, runtime_settings, firmware, dry_run: None };
 
     let machine =
         StateMachine::Poll(State(Poll {})).move_to_next_state(&mut shared_state).unwrap().0;
@@ -100,7 +100,7 @@ fn probe_now() {
     runtime_settings.force_poll().expect("failed to force polling");
 
     let firmware = Metadata::from_path(&create_fake_metadata(FakeDevice::NoUpdate)).unwrap();
-    let mut shared_state = SharedState { settings, runtime_settings, firmware };
+    let mut shared_state = SharedState { settings, runtime_settings, firmware, dry_run: None };
 
     let machine =
         StateMachine::Poll(State(Poll {})).move_to_next_state(&mut shared_state).unwrap().0;
@@ -120,7 +120,7 @@ fn last_poll_in_future() {
     runtime_settings.set_last_polling(Utc::now() + Duration::days(1)).unwrap();
 
     let firmware = Metadata::from_path(&create_fake_metadata(FakeDevice::NoUpdate)).unwrap();
-    let mut shared_state = SharedState { settings, runtime_settings, firmware };
+    let mut shared_state = SharedState { settings, runtime_settings, firmware, dry_run: None };
 
     let machine =
         StateMachine::Poll(State(Poll {})).move_to_next_state(&mut shared_state).unwrap().0;
@@ -141,7 +141,7 @@ fn interval_1_second() {
     runtime_settings.set_last_polling(Utc::now()).unwrap();
 
     let firmware = Metadata::from_path(&create_fake_metadata(FakeDevice::NoUpdate)).unwrap();
-    let mut shared_state = SharedState { settings, runtime_settings, firmware };
+    let mut shared_state = SharedState { settings, runtime_settings, firmware, dry_run: None };
 
     let machine =
         StateMachine::Poll(State(Poll {})).move_to_next_state(&mut shared_state).unwrap().0;
@@ -160,7 +160,7 @@ fn never_polled() {
 
     let runtime_settings = RuntimeSettings::default();
     let firmware = Metadata::from_path(&create_fake_metadata(FakeDevice::NoUpdate)).unwrap();
-    let mut shared_state = SharedState { settings, runtime_settings, firmware };
+    let mut shared_state = SharedState { settings, runtime_settings, firmware, dry_run: None };
 
     let machine =
         StateMachine::Poll(State(Poll {})).move_to_next_state(&mut shared_state).unwrap().0;
@@ -9,7 +9,7 @@ use super::{
 use crate::{
     client::Api,
     firmware::installation_set,
-    object::{self, Info},
+    object::{self, chunks::Chunked, Info},
     update_package::UpdatePackage,
 };
 use slog_scope::error;
@@ -36,7 +36,16 @@ impl StateChangeImpl for State<PrepareDownload> {
     ) -> Result<(StateMachine, actor::StepTransition), failure::Error> {
         crate::logger::buffer().lock().unwrap().start_logging();
         let installation_set = installation_set::inactive()?;
+        let active_set = installation_set::active()?;
         let download_dir = shared_state.settings.update.download_dir.to_owned();
+        let chunked_objects = self
+            .0
+            .update_package
+            .objects(installation_set)
+            .iter()
+            .filter_map(|o| Chunked::from_object(o, None))
+            .collect::<Vec<_>>();
+        let indexes = chunked_objects.iter().map(Chunked::index).collect::<Vec<_>>();
 
         // Prune left over from previous installations
         for entry in WalkDir::new(&download_dir)
@@ -52,11 +61,13 @@ impl StateChangeImpl for State<PrepareDownload> {
                     .objects(installation_set)
                     .iter()
                     .map(object::Info::sha256sum)
+                    .chain(indexes.iter().cloned())
                     .any(|x| x == e.file_name())
             })
         {
             fs::remove_file(entry.path())?;
         }
+        object::chunks::prune_store(&download_dir, &indexes)?;
 
         // Prune corrupted files
         for object in self.0.update_package.filter_objects(
@@ -67,13 +78,16 @@ impl StateChangeImpl for State<PrepareDownload> {
             fs::remove_file(download_dir.join(object.sha256sum()))?;
         }
 
-        // Get shasums of missing or incomplete objects
+        // Get shasums of missing or incomplete objects, along with how
+        // to download them by chunks when they have a chunk index.
+        let active_objects = self.0.update_package.objects(active_set);
         let shasum_list: Vec<_> = self
             .0
             .update_package
             .objects(installation_set)
             .iter()
-            .filter(|o| {
+            .enumerate()
+            .filter(|(_, o)| {
                 let obj_status = o
                     .status(&download_dir)
                     .map_err(|e| {
@@ -83,7 +97,9 @@ impl StateChangeImpl for State<PrepareDownload> {
                 obj_status == object::info::Status::Missing
                     || obj_status == object::info::Status::Incomplete
             })
-            .map(|obj| obj.sha256sum().to_owned())
+            .map(|(i, obj)| {
+                (obj.sha256sum().to_owned(), Chunked::from_object(obj, active_objects.get(i)))
+            })
             .collect();
 
         // Get ownership of remaining data that will be sent to new thread
@@ -97,8 +113,11 @@ impl StateChangeImpl for State<PrepareDownload> {
             let api = Api::new(&server);
             let results = shasum_list
                 .into_iter()
-                .map(|shasum| {
-                    api.download_object(&product_uid, &package_uid, &download_dir, &shasum)
+                .map(|(shasum, chunked)| match chunked {
+                    Some(chunked) => {
+                        chunked.download(&api, &product_uid, &package_uid, &download_dir, &shasum)
+                    }
+                    None => api.download_object(&product_uid, &package_uid, &download_dir, &shasum),
                 })
                 .collect();
             sndr.send(results).expect("Unable to send response about object downlod");
@@ -64,9 +64,28 @@ impl StateChangeImpl for State<Probe> {
                 Ok((StateMachine::Poll(self.into()), actor::StepTransition::Immediate))
             }
 
-            ProbeResponse::Update(u) => {
+            ProbeResponse::Update(u, signature) => {
+                // Ensure the package comes from a trusted source
+                u.validate_signature(
+                    signature.as_deref(),
+                    &shared_state.settings.firmware.metadata_path,
+                )?;
                 // Ensure the package is compatible
                 u.compatible_with(&shared_state.firmware)?;
+
+                // Objects using install modes forbidden on this product
+                // reject the package before anything is downloaded.
+                if let Err(e) = u.supported_by(&shared_state.settings.update.install_modes) {
+                    Api::new(shared_state.server_address()).report(
+                        "error",
+                        &shared_state.firmware,
+                        &u.package_uid(),
+                        None,
+                        Some(e.to_string()),
+                        Some(crate::logger::buffer().lock().unwrap().to_string()),
+                    )?;
+                    return Err(e.into());
+                }
                 // Store timestamp of last polling
                 shared_state.runtime_settings.set_last_polling(Utc::now())?;
 
@@ -76,6 +95,14 @@ impl StateChangeImpl for State<Probe> {
                     );
                     debug!("Moving to Idle state as this update package is already installed.");
                     Ok((StateMachine::Idle(self.into()), actor::StepTransition::Immediate))
+                } else if shared_state.dry_run.as_ref().map(|d| &d.package_uid)
+                    == Some(&u.package_uid())
+                {
+                    info!(
+                        "Not applying the update package. Same package has already been dry-run."
+                    );
+                    debug!("Moving to Idle state as this update package was already dry-run.");
+                    Ok((StateMachine::Idle(self.into()), actor::StepTransition::Immediate))
                 } else {
                     debug!("Moving to PrepareDownload state to process the update package.");
                     Ok((
@@ -114,7 +141,7 @@ mod tests {
         let settings = Settings::default();
         let runtime_settings = RuntimeSettings::new().load(tmpfile.to_str().unwrap()).unwrap();
         let firmware = Metadata::from_path(&create_fake_metadata(FakeDevice::NoUpdate)).unwrap();
-        let mut shared_state = SharedState { settings, runtime_settings, firmware };
+        let mut shared_state = SharedState { settings, runtime_settings, firmware, dry_run: None };
 
         let machine =
             StateMachine::Probe(State(Probe {})).move_to_next_state(&mut shared_state).unwrap().0;
@@ -132,10 +159,11 @@ mod tests {
 
         let mock = create_mock_server(FakeServer::HasUpdate);
 
-        let settings = Settings::default();
+        let mut settings = Settings::default();
+        settings.update.install_modes.push("test".to_string());
         let runtime_settings = RuntimeSettings::new().load(tmpfile.to_str().unwrap()).unwrap();
         let firmware = Metadata::from_path(&create_fake_metadata(FakeDevice::HasUpdate)).unwrap();
-        let mut shared_state = SharedState { settings, runtime_settings, firmware };
+        let mut shared_state = SharedState { settings, runtime_settings, firmware, dry_run: None };
 
         let machine =
             StateMachine::Probe(State(Probe {})).move_to_next_state(&mut shared_state).unwrap().0;
@@ -157,7 +185,7 @@ mod tests {
         let runtime_settings = RuntimeSettings::new().load(tmpfile.to_str().unwrap()).unwrap();
         let firmware =
             Metadata::from_path(&create_fake_metadata(FakeDevice::InvalidHardware)).unwrap();
-        let mut shared_state = SharedState { settings, runtime_settings, firmware };
+        let mut shared_state = SharedState { settings, runtime_settings, firmware, dry_run: None };
 
         let machine = StateMachine::Probe(State(Probe {})).move_to_next_state(&mut shared_state);
 
@@ -166,6 +194,122 @@ mod tests {
         assert!(machine.is_err(), "Did not catch an incompatible hardware");
     }
 
+    #[test]
+    fn signed_update_available() {
+        use crate::update_package::{
+            signature::{tests::sign, PUBLIC_KEY},
+            tests::{get_update_json, SHA256SUM},
+        };
+        use openssl::pkey::PKey;
+
+        let tmpfile = NamedTempFile::new().unwrap();
+        let tmpfile = tmpfile.path();
+        fs::remove_file(tmpfile).unwrap();
+
+        let key = PKey::generate_ed25519().unwrap();
+        let metadata_path = tempfile::tempdir().unwrap();
+        fs::write(metadata_path.path().join(PUBLIC_KEY), key.public_key_to_pem().unwrap()).unwrap();
+        let mock = create_mock_server(FakeServer::HasSignedUpdate(sign(
+            &key,
+            &get_update_json(SHA256SUM).to_string(),
+        )));
+
+        let mut settings = Settings::default();
+        settings.update.install_modes.push("test".to_string());
+        settings.firmware.metadata_path = metadata_path.path().to_path_buf();
+        let runtime_settings = RuntimeSettings::new().load(tmpfile.to_str().unwrap()).unwrap();
+        let firmware = Metadata::from_path(&create_fake_metadata(FakeDevice::HasUpdate)).unwrap();
+        let mut shared_state = SharedState { settings, runtime_settings, firmware, dry_run: None };
+
+        let machine =
+            StateMachine::Probe(State(Probe {})).move_to_next_state(&mut shared_state).unwrap().0;
+
+        mock.assert();
+
+        assert_state!(machine, PrepareDownload);
+    }
+
+    #[test]
+    fn malformed_signature_without_key() {
+        let tmpfile = NamedTempFile::new().unwrap();
+        let tmpfile = tmpfile.path();
+        fs::remove_file(tmpfile).unwrap();
+
+        // Signatures are not checked when no public key is installed
+        let metadata_path = tempfile::tempdir().unwrap();
+        let mock = create_mock_server(FakeServer::HasSignedUpdate("not base64!".to_string()));
+
+        let mut settings = Settings::default();
+        settings.update.install_modes.push("test".to_string());
+        settings.firmware.metadata_path = metadata_path.path().to_path_buf();
+        let runtime_settings = RuntimeSettings::new().load(tmpfile.to_str().unwrap()).unwrap();
+        let firmware = Metadata::from_path(&create_fake_metadata(FakeDevice::HasUpdate)).unwrap();
+        let mut shared_state = SharedState { settings, runtime_settings, firmware, dry_run: None };
+
+        let machine =
+            StateMachine::Probe(State(Probe {})).move_to_next_state(&mut shared_state).unwrap().0;
+
+        mock.assert();
+
+        assert_state!(machine, PrepareDownload);
+    }
+
+    #[test]
+    fn unsigned_update_rejected() {
+        use crate::update_package::signature::PUBLIC_KEY;
+        use openssl::pkey::PKey;
+
+        let tmpfile = NamedTempFile::new().unwrap();
+        let tmpfile = tmpfile.path();
+        fs::remove_file(tmpfile).unwrap();
+
+        let key = PKey::generate_ed25519().unwrap();
+        let metadata_path = tempfile::tempdir().unwrap();
+        fs::write(metadata_path.path().join(PUBLIC_KEY), key.public_key_to_pem().unwrap()).unwrap();
+        let mock = create_mock_server(FakeServer::HasUpdate);
+
+        let mut settings = Settings::default();
+        settings.firmware.metadata_path = metadata_path.path().to_path_buf();
+        let runtime_settings = RuntimeSettings::new().load(tmpfile.to_str().unwrap()).unwrap();
+        let firmware = Metadata::from_path(&create_fake_metadata(FakeDevice::HasUpdate)).unwrap();
+        let mut shared_state = SharedState { settings, runtime_settings, firmware, dry_run: None };
+
+        let machine = StateMachine::Probe(State(Probe {})).move_to_next_state(&mut shared_state);
+
+        mock.assert();
+
+        assert!(machine.is_err(), "Did not reject the unsigned package");
+    }
+
+    #[test]
+    fn unsupported_install_modes() {
+        let tmpfile = NamedTempFile::new().unwrap();
+        let tmpfile = tmpfile.path();
+        fs::remove_file(tmpfile).unwrap();
+
+        let mock = create_mock_server(FakeServer::HasUpdate);
+        let report_mock = mockito::mock("POST", "/report")
+            .match_body(mockito::Matcher::PartialJson(serde_json::json!({
+                "status": "error",
+                "error-message": "Unsupported install modes: test",
+            })))
+            .with_status(200)
+            .create();
+
+        let mut settings = Settings::default();
+        settings.update.install_modes = vec!["copy".to_string(), "raw".to_string()];
+        let runtime_settings = RuntimeSettings::new().load(tmpfile.to_str().unwrap()).unwrap();
+        let firmware = Metadata::from_path(&create_fake_metadata(FakeDevice::HasUpdate)).unwrap();
+        let mut shared_state = SharedState { settings, runtime_settings, firmware, dry_run: None };
+
+        let machine = StateMachine::Probe(State(Probe {})).move_to_next_state(&mut shared_state);
+
+        mock.assert();
+        report_mock.assert();
+
+        assert!(machine.is_err(), "Did not reject the unsupported install modes");
+    }
+
     #[test]
     fn extra_poll_interval() {
         let tmpfile = NamedTempFile::new().unwrap();
@@ -177,7 +321,7 @@ mod tests {
         let settings = Settings::default();
         let runtime_settings = RuntimeSettings::new().load(tmpfile.to_str().unwrap()).unwrap();
         let firmware = Metadata::from_path(&create_fake_metadata(FakeDevice::ExtraPoll)).unwrap();
-        let mut shared_state = SharedState { settings, runtime_settings, firmware };
+        let mut shared_state = SharedState { settings, runtime_settings, firmware, dry_run: None };
 
         let machine =
             StateMachine::Probe(State(Probe {})).move_to_next_state(&mut shared_state).unwrap().0;
@@ -209,13 +353,52 @@ mod tests {
             )
             .unwrap();
 
-        if let ProbeResponse::Update(u) = probe {
+        if let ProbeResponse::Update(u, _) = probe {
             runtime_settings.set_applied_package_uid(&u.package_uid()).unwrap();
         }
 
-        let settings = Settings::default();
+        let mut settings = Settings::default();
+        settings.update.install_modes.push("test".to_string());
+        let firmware = Metadata::from_path(&create_fake_metadata(FakeDevice::HasUpdate)).unwrap();
+        let mut shared_state = SharedState { settings, runtime_settings, firmware, dry_run: None };
+
+        let machine =
+            StateMachine::Probe(State(Probe {})).move_to_next_state(&mut shared_state).unwrap().0;
+
+        mock.assert();
+
+        assert_state!(machine, Idle);
+    }
+
+    #[test]
+    fn skip_dry_run_package_uid() {
+        use crate::states::install::DryRun;
+
+        let tmpfile = NamedTempFile::new().unwrap();
+        let tmpfile = tmpfile.path();
+        fs::remove_file(tmpfile).unwrap();
+
+        let mock = create_mock_server(FakeServer::HasUpdate).expect(2);
+
+        let probe = Api::new(&Settings::default().network.server_address)
+            .probe(
+                &RuntimeSettings::default(),
+                &Metadata::from_path(&create_fake_metadata(FakeDevice::HasUpdate)).unwrap(),
+            )
+            .unwrap();
+        let package_uid = match probe {
+            ProbeResponse::Update(u, _) => u.package_uid(),
+            p => panic!("Unexpected probe response: {:?}", p),
+        };
+
+        let mut settings = Settings::default();
+        settings.update.install_modes.push("test".to_string());
+        settings.update.dry_run = true;
+        let runtime_settings = RuntimeSettings::new().load(tmpfile.to_str().unwrap()).unwrap();
         let firmware = Metadata::from_path(&create_fake_metadata(FakeDevice::HasUpdate)).unwrap();
-        let mut shared_state = SharedState { settings, runtime_settings, firmware };
+        let dry_run =
+            Some(DryRun { package_uid, installation_set: "1".to_string(), objects: Vec::new() });
+        let mut shared_state = SharedState { settings, runtime_settings, firmware, dry_run };
 
         let machine =
             StateMachine::Probe(State(Probe {})).move_to_next_state(&mut shared_state).unwrap().0;
@@ -238,7 +421,7 @@ mod tests {
         let settings = Settings::default();
         let runtime_settings = RuntimeSettings::new().load(tmpfile.to_str().unwrap()).unwrap();
         let firmware = Metadata::from_path(&create_fake_metadata(FakeDevice::NoUpdate)).unwrap();
-        let mut shared_state = SharedState { settings, runtime_settings, firmware };
+        let mut shared_state = SharedState { settings, runtime_settings, firmware, dry_run: None };
 
         let machine = StateMachine::Probe(State(Probe {}))
             .move_to_next_state(&mut shared_state)
@@ -72,7 +72,7 @@ mod test {
         let settings = Settings::default();
         let runtime_settings = RuntimeSettings::default();
         let firmware = Metadata::from_path(&create_fake_metadata(FakeDevice::NoUpdate)).unwrap();
-        let shared_state = SharedState { settings, runtime_settings, firmware };
+        let shared_state = SharedState { settings, runtime_settings, firmware, dry_run: None };
 
         (State(Reboot { update_package: get_update_package() }), shared_state)
     }
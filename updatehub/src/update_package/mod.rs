@@ -14,9 +14,11 @@ use pkg_schema::Object;
 use serde::Deserialize;
 use serde_json;
 use slog_scope::error;
+use std::path::Path;
 
+pub(crate) mod signature;
 mod supported_hardware;
-use self::supported_hardware::SupportedHardware;
+use self::{signature::Signature, supported_hardware::SupportedHardware};
 
 #[cfg(test)]
 pub(crate) mod tests;
@@ -44,6 +46,12 @@ pub(crate) struct UpdatePackage {
 pub(crate) enum UpdatePackageError {
     #[fail(display = "Incompatible with hardware: {}", _0)]
     IncompatibleHardware(String),
+    #[fail(display = "Unsupported install modes: {}", _0)]
+    UnsupportedInstallModes(String),
+    #[fail(display = "Update package is not signed")]
+    MissingSignature,
+    #[fail(display = "Update package signature does not match the public key")]
+    InvalidSignature,
 }
 
 impl UpdatePackage {
@@ -62,6 +70,46 @@ impl UpdatePackage {
         self.supported_hardware.compatible_with(&firmware.hardware)
     }
 
+    /// Ensures the package is signed with the public key installed in
+    /// the firmware `metadata_path`. Unsigned packages are only accepted
+    /// when no key is installed.
+    pub(crate) fn validate_signature(
+        &self,
+        signature: Option<&str>,
+        metadata_path: &Path,
+    ) -> Result<(), failure::Error> {
+        let key = metadata_path.join(signature::PUBLIC_KEY);
+        if !key.exists() {
+            return Ok(());
+        }
+
+        match signature {
+            Some(signature) => Signature::from_base64_str(signature)?.validate(&key, self),
+            None => Err(UpdatePackageError::MissingSignature.into()),
+        }
+    }
+
+    /// Ensures all objects of the package use one of the supported
+    /// `install_modes`, listing the offending modes otherwise.
+    pub(crate) fn supported_by(&self, install_modes: &[String]) -> Result<(), UpdatePackageError> {
+        let mut unsupported = self
+            .objects
+            .0
+            .iter()
+            .chain(self.objects.1.iter())
+            .map(install_mode)
+            .filter(|mode| !install_modes.iter().any(|m| m == mode))
+            .collect::<Vec<_>>();
+        unsupported.sort();
+        unsupported.dedup();
+
+        if !unsupported.is_empty() {
+            return Err(UpdatePackageError::UnsupportedInstallModes(unsupported.join(", ")));
+        }
+
+        Ok(())
+    }
+
     pub(crate) fn objects(&self, installation_set: InstallationSet) -> &Vec<Object> {
         match installation_set {
             InstallationSet::A => &self.objects.0,
@@ -95,3 +143,21 @@ impl UpdatePackage {
             .collect()
     }
 }
+
+// Install mode of the object, as found in the package metadata.
+fn install_mode(object: &Object) -> &str {
+    match object {
+        Object::Copy(_) => "copy",
+        Object::External(o) => &o.mode,
+        Object::Flash(_) => "flash",
+        Object::Imxkobs(_) => "imxkobs",
+        Object::Mender(_) => "mender",
+        Object::Raw(_) => "raw",
+        Object::RawDelta(_) => "raw-delta",
+        Object::Shell(_) => "shell",
+        Object::Tarball(_) => "tarball",
+        Object::Test(_) => "test",
+        Object::Ubifs(_) => "ubifs",
+        Object::Zephyr(_) => "zephyr",
+    }
+}
@@ -0,0 +1,129 @@
+// Copyright (C) 2019 O.S. Systems Sofware LTDA
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use super::{UpdatePackage, UpdatePackageError};
+use openssl::{
+    hash::MessageDigest,
+    pkey::{Id, PKey},
+    rsa::Padding,
+    sign::{RsaPssSaltlen, Verifier},
+};
+use std::{fs, path::Path};
+
+/// Public key, inside the firmware metadata path, which update packages
+/// must be signed with.
+pub(crate) const PUBLIC_KEY: &str = "key.pem";
+
+/// Detached signature of the raw update package, sent base64 encoded
+/// by the server in the `signature` header of the probe response.
+#[derive(Debug, PartialEq)]
+pub(crate) struct Signature(Vec<u8>);
+
+impl Signature {
+    pub(crate) fn from_base64_str(s: &str) -> Result<Self, failure::Error> {
+        Ok(Signature(base64::decode(s.trim())?))
+    }
+
+    /// Checks the signature of the `package` content against the PEM
+    /// encoded public key at `key`. RSA keys verify RSA-PSS signatures
+    /// over its SHA-256 digest while Ed25519 keys verify plain Ed25519
+    /// signatures.
+    pub(crate) fn validate(
+        &self,
+        key: &Path,
+        package: &UpdatePackage,
+    ) -> Result<(), failure::Error> {
+        let key = PKey::public_key_from_pem(&fs::read(key)?)?;
+        let valid = match key.id() {
+            Id::RSA => {
+                let mut verifier = Verifier::new(MessageDigest::sha256(), &key)?;
+                verifier.set_rsa_padding(Padding::PKCS1_PSS)?;
+                verifier.set_rsa_pss_saltlen(RsaPssSaltlen::DIGEST_LENGTH)?;
+                verifier.verify_oneshot(&self.0, package.raw.as_bytes())?
+            }
+            Id::ED25519 => Verifier::new_without_digest(&key)?
+                .verify_oneshot(&self.0, package.raw.as_bytes())?,
+            id => failure::bail!("Unsupported public key type: {:?}", id),
+        };
+
+        if !valid {
+            return Err(UpdatePackageError::InvalidSignature.into());
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::update_package::tests::{get_update_json, SHA256SUM};
+    use openssl::{pkey::Private, rsa::Rsa, sign::Signer};
+    use tempfile::NamedTempFile;
+
+    /// Signs `content` with `key` as the server does, returning the
+    /// base64 encoded signature.
+    pub(crate) fn sign(key: &PKey<Private>, content: &str) -> String {
+        let signature = match key.id() {
+            Id::RSA => {
+                let mut signer = Signer::new(MessageDigest::sha256(), key).unwrap();
+                signer.set_rsa_padding(Padding::PKCS1_PSS).unwrap();
+                signer.set_rsa_pss_saltlen(RsaPssSaltlen::DIGEST_LENGTH).unwrap();
+                signer.sign_oneshot_to_vec(content.as_bytes()).unwrap()
+            }
+            _ => Signer::new_without_digest(key)
+                .unwrap()
+                .sign_oneshot_to_vec(content.as_bytes())
+                .unwrap(),
+        };
+
+        base64::encode(&signature)
+    }
+
+    /// Writes the public part of `key` as a PEM file.
+    pub(crate) fn public_key(key: &PKey<Private>) -> NamedTempFile {
+        let file = NamedTempFile::new().unwrap();
+        fs::write(file.path(), key.public_key_to_pem().unwrap()).unwrap();
+        file
+    }
+
+    fn keys() -> Vec<PKey<Private>> {
+        vec![
+            PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap(),
+            PKey::generate_ed25519().unwrap(),
+        ]
+    }
+
+    #[test]
+    fn valid_signature() {
+        let content = get_update_json(SHA256SUM).to_string();
+        let package = UpdatePackage::parse(&content).unwrap();
+
+        for key in keys() {
+            let signature = Signature::from_base64_str(&sign(&key, &content)).unwrap();
+            signature.validate(public_key(&key).path(), &package).unwrap();
+        }
+    }
+
+    #[test]
+    fn tampered_package() {
+        let content = get_update_json(SHA256SUM).to_string();
+        let package = UpdatePackage::parse(&content.replace("1.0", "1.1")).unwrap();
+
+        for key in keys() {
+            let signature = Signature::from_base64_str(&sign(&key, &content)).unwrap();
+            assert!(signature.validate(public_key(&key).path(), &package).is_err());
+        }
+    }
+
+    #[test]
+    fn signed_with_other_key() {
+        let content = get_update_json(SHA256SUM).to_string();
+        let package = UpdatePackage::parse(&content).unwrap();
+
+        let (key, other) = (keys().remove(1), PKey::generate_ed25519().unwrap());
+        let signature = Signature::from_base64_str(&sign(&other, &content)).unwrap();
+        assert!(signature.validate(public_key(&key).path(), &package).is_err());
+    }
+}
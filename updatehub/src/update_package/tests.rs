@@ -118,3 +118,15 @@ fn complete_object_file() {
         1
     );
 }
+
+#[test]
+fn unsupported_install_modes() {
+    let update_package = get_update_package();
+    let modes = |modes: &[&str]| modes.iter().map(|m| m.to_string()).collect::<Vec<_>>();
+
+    assert!(update_package.supported_by(&modes(&["copy", "test"])).is_ok());
+    assert_eq!(
+        update_package.supported_by(&modes(&["copy", "raw"])).unwrap_err().to_string(),
+        "Unsupported install modes: test"
+    );
+}
@@ -2,13 +2,16 @@
 //
 // SPDX-License-Identifier: Apache-2.0
 
-use crate::utils::mtd;
-use failure::ensure;
+use crate::utils::{disk, mtd};
+use failure::{ensure, format_err};
 use pkg_schema::definitions::{
     target_permissions::{Gid, Uid},
     TargetType,
 };
-use std::path::PathBuf;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
 
 /// Utility funtions for [TargetType](pkg_schema::definitions::TargetType)
 pub(crate) trait TargetTypeExt {
@@ -16,8 +19,13 @@ pub(crate) trait TargetTypeExt {
     /// device exists, use have write permission.
     fn valid(&self) -> Result<&Self, failure::Error>;
 
-    /// Gets device's path for mounting.
+    /// Gets device's path for mounting, or the directory itself for
+    /// `path` targets.
     fn get_target(&self) -> Result<PathBuf, failure::Error>;
+
+    /// Checks whether the target is a block device, given by its path
+    /// or found by its partition or filesystem identifiers.
+    fn is_block_device(&self) -> bool;
 }
 
 impl TargetTypeExt for TargetType {
@@ -50,6 +58,24 @@ impl TargetTypeExt for TargetType {
                 );
                 &self
             }
+            TargetType::PartLabel(_) | TargetType::PartUUID(_) | TargetType::FsUUID(_) => {
+                let dev = self.get_target()?;
+                ensure!(
+                    !dev.metadata()?.permissions().readonly(),
+                    "User doesn't have write permission on target device: {:?}",
+                    dev
+                );
+                self
+            }
+            TargetType::Path(p) => {
+                ensure!(p.is_dir(), "Target path {:?} is not a directory", p);
+                ensure!(
+                    nix::unistd::access(p.as_path(), nix::unistd::AccessFlags::W_OK).is_ok(),
+                    "User doesn't have write permission on target path: {:?}",
+                    p
+                );
+                self
+            }
         })
     }
 
@@ -58,6 +84,20 @@ impl TargetTypeExt for TargetType {
             TargetType::Device(p) => Ok(p.clone()),
             TargetType::UBIVolume(s) => mtd::target_device_from_ubi_volume_name(s),
             TargetType::MTDName(s) => mtd::target_device_from_mtd_name(s),
+            TargetType::PartLabel(s) => disk::target_device_from_partlabel(s),
+            TargetType::PartUUID(s) => disk::target_device_from_partuuid(s),
+            TargetType::FsUUID(s) => disk::target_device_from_fs_uuid(s),
+            TargetType::Path(p) => Ok(p.clone()),
+        }
+    }
+
+    fn is_block_device(&self) -> bool {
+        match self {
+            TargetType::Device(_)
+            | TargetType::PartLabel(_)
+            | TargetType::PartUUID(_)
+            | TargetType::FsUUID(_) => true,
+            TargetType::UBIVolume(_) | TargetType::MTDName(_) | TargetType::Path(_) => false,
         }
     }
 }
@@ -65,30 +105,87 @@ impl TargetTypeExt for TargetType {
 /// Utility funtions for [Gid](pkg_schema::definitions::target_permissions::Gid)
 /// and [Uid](pkg_schema::definitions::target_permissions::Uid)
 pub(crate) trait IdExt {
-    /// Gets numeric id;
-    fn as_u32(&self) -> u32;
+    /// Gets numeric id, looking names up in the `/etc/passwd` or
+    /// `/etc/group` of the filesystem mounted at `root`.
+    fn resolve(&self, root: &Path) -> Result<u32, failure::Error>;
 }
 
 impl IdExt for Gid {
-    fn as_u32(&self) -> u32 {
+    fn resolve(&self, root: &Path) -> Result<u32, failure::Error> {
         match self {
-            Gid::Name(s) => {
-                let s = std::ffi::CString::new(s.as_str()).unwrap();
-                unsafe { *nix::libc::getgrnam(s.as_ptr()) }.gr_gid
-            }
-            Gid::Number(n) => *n,
+            Gid::Name(s) => find_id(&root.join("etc/group"), s)?
+                .ok_or_else(|| format_err!("Group '{}' not found on target's /etc/group", s)),
+            Gid::Number(n) => Ok(*n),
         }
     }
 }
 
 impl IdExt for Uid {
-    fn as_u32(&self) -> u32 {
+    fn resolve(&self, root: &Path) -> Result<u32, failure::Error> {
         match self {
-            Uid::Name(s) => {
-                let s = std::ffi::CString::new(s.as_str()).unwrap();
-                unsafe { *nix::libc::getpwnam(s.as_ptr()) }.pw_uid
-            }
-            Uid::Number(n) => *n,
+            Uid::Name(s) => find_id(&root.join("etc/passwd"), s)?
+                .ok_or_else(|| format_err!("User '{}' not found on target's /etc/passwd", s)),
+            Uid::Number(n) => Ok(*n),
         }
     }
 }
+
+// Finds the id of `name` on `database`, which follows the passwd(5) and
+// group(5) format where the name and id are the first and third fields.
+fn find_id(database: &Path, name: &str) -> Result<Option<u32>, failure::Error> {
+    let content = fs::read_to_string(database)
+        .map_err(|e| format_err!("Unable to read {:?}: {}", database, e))?;
+
+    for line in content.lines().filter(|l| !l.trim().is_empty() && !l.starts_with('#')) {
+        let fields = line.split(':').collect::<Vec<_>>();
+        ensure!(fields.len() >= 3, "Malformed entry on {:?}: {}", database, line);
+
+        if fields[0] == name {
+            return Ok(Some(fields[2].parse().map_err(|_| {
+                format_err!("Invalid id for '{}' on {:?}: {}", name, database, fields[2])
+            })?));
+        }
+    }
+
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use tempfile::tempdir;
+
+    fn fake_root() -> tempfile::TempDir {
+        let root = tempdir().unwrap();
+        fs::create_dir(root.path().join("etc")).unwrap();
+        fs::write(
+            root.path().join("etc/passwd"),
+            "root:x:0:0:root:/root:/bin/sh\n# comment\n\nupdatehub:x:1234:1000::/home:/bin/false\n",
+        )
+        .unwrap();
+        fs::write(root.path().join("etc/group"), "root:x:0:\nupdatehub:x:4321:user1,user2\n")
+            .unwrap();
+
+        root
+    }
+
+    #[test]
+    fn resolve_names() {
+        let root = fake_root();
+
+        assert_eq!(Uid::Name("updatehub".to_string()).resolve(root.path()).unwrap(), 1234);
+        assert_eq!(Gid::Name("updatehub".to_string()).resolve(root.path()).unwrap(), 4321);
+        assert_eq!(Uid::Number(10).resolve(root.path()).unwrap(), 10);
+        assert_eq!(Gid::Number(10).resolve(root.path()).unwrap(), 10);
+    }
+
+    #[test]
+    fn resolve_unknown_names() {
+        let root = fake_root();
+
+        assert!(Uid::Name("unknown".to_string()).resolve(root.path()).is_err());
+        assert!(Gid::Name("unknown".to_string()).resolve(root.path()).is_err());
+        assert!(Uid::Name("root".to_string()).resolve(Path::new("/nonexistent")).is_err());
+    }
+}
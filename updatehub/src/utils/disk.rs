@@ -0,0 +1,385 @@
+// Copyright (C) 2019 O.S. Systems Sofware LTDA
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use failure::format_err;
+use std::{
+    fs,
+    io::{self, Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+};
+
+const DEV: &str = "/dev";
+const BLOCK_SYSFS: &str = "/sys/class/block";
+
+const SECTOR_SIZE: u64 = 512;
+const GPT_SIGNATURE: &[u8] = b"EFI PART";
+const GPT_ENTRY_SIZE: usize = 128;
+// Size of the partition table reserved by the specification, which
+// holds 128 entries of 128 bytes.
+const GPT_MAX_TABLE_SIZE: usize = 128 * GPT_ENTRY_SIZE;
+
+/// Partition found on a disk's partition table.
+#[derive(Debug, PartialEq)]
+struct Partition {
+    number: u32,
+    label: Option<String>,
+    uuid: String,
+}
+
+pub(crate) fn target_device_from_partlabel(label: &str) -> Result<PathBuf, failure::Error> {
+    find_device(Path::new(DEV), Path::new(BLOCK_SYSFS), "by-partlabel", label, |dev, sysfs| {
+        find_partition(dev, sysfs, |p| p.label.as_ref().map(|l| l == label).unwrap_or(false))
+    })
+    .ok_or_else(|| format_err!("Unable to find partition labeled {}", label))
+}
+
+pub(crate) fn target_device_from_partuuid(uuid: &str) -> Result<PathBuf, failure::Error> {
+    let uuid = uuid.to_lowercase();
+    find_device(Path::new(DEV), Path::new(BLOCK_SYSFS), "by-partuuid", &uuid, |dev, sysfs| {
+        find_partition(dev, sysfs, |p| p.uuid == uuid)
+    })
+    .ok_or_else(|| format_err!("Unable to find partition with {} UUID", uuid))
+}
+
+pub(crate) fn target_device_from_fs_uuid(uuid: &str) -> Result<PathBuf, failure::Error> {
+    find_device(Path::new(DEV), Path::new(BLOCK_SYSFS), "by-uuid", uuid, |dev, sysfs| {
+        find_filesystem(dev, sysfs, uuid)
+    })
+    .ok_or_else(|| format_err!("Unable to find filesystem with {} UUID", uuid))
+}
+
+// Looks `name` up on the `/dev/disk/by-*` links maintained by udev,
+// falling back to `scan` the block devices when they are absent.
+fn find_device<F>(dev: &Path, sysfs: &Path, by: &str, name: &str, scan: F) -> Option<PathBuf>
+where
+    F: FnOnce(&Path, &Path) -> Option<PathBuf>,
+{
+    fs::canonicalize(dev.join("disk").join(by).join(name)).ok().or_else(|| scan(dev, sysfs))
+}
+
+// Finds the partition `matches` accepts by reading the partition table
+// of each disk listed in the block class of `sysfs`.
+fn find_partition<F>(dev: &Path, sysfs: &Path, matches: F) -> Option<PathBuf>
+where
+    F: Fn(&Partition) -> bool,
+{
+    block_devices(sysfs).into_iter().find_map(|name| {
+        // Partitions are listed below their disk in sysfs.
+        let number = fs::read_to_string(sysfs.join(&name).join("partition")).ok()?;
+        let number = number.trim().parse::<u32>().ok()?;
+        let disk = fs::canonicalize(sysfs.join(&name)).ok()?;
+        let disk = disk.parent()?.file_name()?;
+
+        read_partitions(fs::File::open(dev.join(disk)).ok()?)
+            .ok()?
+            .iter()
+            .find(|p| p.number == number && matches(p))
+            .map(|_| dev.join(&name))
+    })
+}
+
+// Finds the block device holding the filesystem identified by `uuid`,
+// which is compared ignoring case as FAT volume ids are upper case.
+fn find_filesystem(dev: &Path, sysfs: &Path, uuid: &str) -> Option<PathBuf> {
+    block_devices(sysfs).into_iter().map(|name| dev.join(name)).find(|device| match fs::File::open(
+        device,
+    )
+    .and_then(filesystem_uuid)
+    {
+        Ok(Some(found)) => found.eq_ignore_ascii_case(uuid),
+        _ => false,
+    })
+}
+
+fn block_devices(sysfs: &Path) -> Vec<String> {
+    fs::read_dir(sysfs)
+        .map(|entries| {
+            entries
+                .filter_map(Result::ok)
+                .filter_map(|e| e.file_name().into_string().ok())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+// Reads the GPT, or MBR, partition table of `disk`. Partitions on MBR
+// tables are identified by the disk signature and partition number, and
+// have no label. Extended partitions are not supported.
+fn read_partitions<R: Read + Seek>(mut disk: R) -> io::Result<Vec<Partition>> {
+    let header = read_at(&mut disk, SECTOR_SIZE, SECTOR_SIZE as usize)?;
+    if header.starts_with(GPT_SIGNATURE) {
+        let entries_lba = u64::from_le_bytes(array(&header[72..80]));
+        let entries = u32::from_le_bytes(array(&header[80..84]));
+        let entry_size = u32::from_le_bytes(array(&header[84..88])) as usize;
+        let table_size = match (entries as usize).checked_mul(entry_size) {
+            Some(size) if entry_size >= GPT_ENTRY_SIZE && size <= GPT_MAX_TABLE_SIZE => size,
+            _ => return Err(io::Error::new(io::ErrorKind::InvalidData, "Invalid GPT header")),
+        };
+        let table = read_at(&mut disk, entries_lba * SECTOR_SIZE, table_size)?;
+
+        return Ok(table
+            .chunks(entry_size)
+            .enumerate()
+            .filter(|(_, entry)| entry[..16].iter().any(|b| *b != 0))
+            .map(|(i, entry)| {
+                let name = entry[56..128]
+                    .chunks(2)
+                    .map(|c| u16::from_le_bytes([c[0], c[1]]))
+                    .take_while(|c| *c != 0)
+                    .collect::<Vec<_>>();
+                Partition {
+                    number: i as u32 + 1,
+                    label: Some(String::from_utf16_lossy(&name)),
+                    uuid: guid(&entry[16..32]),
+                }
+            })
+            .collect());
+    }
+
+    let mbr = read_at(&mut disk, 0, SECTOR_SIZE as usize)?;
+    if mbr[510..512] != [0x55, 0xAA] {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "No partition table found"));
+    }
+    let signature = u32::from_le_bytes(array(&mbr[440..444]));
+
+    Ok(mbr[446..510]
+        .chunks(16)
+        .enumerate()
+        .filter(|(_, entry)| entry[4] != 0)
+        .map(|(i, _)| Partition {
+            number: i as u32 + 1,
+            label: None,
+            uuid: format!("{:08x}-{:02x}", signature, i + 1),
+        })
+        .collect())
+}
+
+// Reads the UUID of the ext2/3/4, XFS, Btrfs or FAT filesystem on
+// `device`, in the same format used by udev.
+fn filesystem_uuid<R: Read + Seek>(mut device: R) -> io::Result<Option<String>> {
+    let ext = read_at(&mut device, 1024, 1024)?;
+    if ext[56..58] == [0x53, 0xEF] {
+        return Ok(Some(uuid(&ext[104..120])));
+    }
+
+    let boot = read_at(&mut device, 0, 512)?;
+    if boot.starts_with(b"XFSB") {
+        return Ok(Some(uuid(&boot[32..48])));
+    }
+    if &boot[82..87] == b"FAT32" {
+        return Ok(Some(fat_volume_id(&boot[67..71])));
+    }
+    if &boot[54..57] == b"FAT" {
+        return Ok(Some(fat_volume_id(&boot[39..43])));
+    }
+
+    match read_at(&mut device, 0x10000, 0x48) {
+        Ok(btrfs) if &btrfs[0x40..0x48] == b"_BHRfS_M" => Ok(Some(uuid(&btrfs[0x20..0x30]))),
+        _ => Ok(None),
+    }
+}
+
+fn read_at<R: Read + Seek>(input: &mut R, offset: u64, len: usize) -> io::Result<Vec<u8>> {
+    let mut buf = vec![0; len];
+    input.seek(SeekFrom::Start(offset))?;
+    input.read_exact(&mut buf)?;
+
+    Ok(buf)
+}
+
+fn array<T: Default + Copy + AsMut<[u8]>>(bytes: &[u8]) -> T {
+    let mut array = T::default();
+    array.as_mut().copy_from_slice(bytes);
+    array
+}
+
+// Formats a UUID stored in big-endian byte order.
+fn uuid(bytes: &[u8]) -> String {
+    let hex = hex::encode(bytes);
+    format!("{}-{}-{}-{}-{}", &hex[0..8], &hex[8..12], &hex[12..16], &hex[16..20], &hex[20..32])
+}
+
+// Formats a GUID, which first three fields are stored in little-endian
+// byte order.
+fn guid(bytes: &[u8]) -> String {
+    let mut swapped = bytes.to_vec();
+    swapped[0..4].reverse();
+    swapped[4..6].reverse();
+    swapped[6..8].reverse();
+    uuid(&swapped)
+}
+
+fn fat_volume_id(bytes: &[u8]) -> String {
+    format!("{:02X}{:02X}-{:02X}{:02X}", bytes[3], bytes[2], bytes[1], bytes[0])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use std::{io::Cursor, os::unix::fs::symlink};
+    use tempfile::tempdir;
+
+    const ROOTFS_UUID: &str = "6a8c3e6d-1b2f-4c9e-9d8a-0f1e2d3c4b5a";
+
+    /// Creates a disk image with a GPT holding a `boot` and `rootfs`
+    /// partitions, the later with `ROOTFS_UUID` as unique GUID.
+    fn gpt_image() -> Vec<u8> {
+        let mut image = vec![0; 64 * 1024];
+        let header = &mut image[512..1024];
+        header[..8].copy_from_slice(GPT_SIGNATURE);
+        header[72..80].copy_from_slice(&2u64.to_le_bytes());
+        header[80..84].copy_from_slice(&4u32.to_le_bytes());
+        header[84..88].copy_from_slice(&128u32.to_le_bytes());
+
+        for (i, label) in ["boot", "rootfs"].iter().enumerate() {
+            let entry = &mut image[1024 + i * 128..1024 + (i + 1) * 128];
+            entry[..16].copy_from_slice(&[0xAA; 16]);
+            entry[16..32].copy_from_slice(&[i as u8; 16]);
+            label
+                .encode_utf16()
+                .flat_map(u16::to_le_bytes)
+                .enumerate()
+                .for_each(|(n, b)| entry[56 + n] = b);
+        }
+        let mut uuid = hex::decode(ROOTFS_UUID.replace('-', "")).unwrap();
+        uuid[0..4].reverse();
+        uuid[4..6].reverse();
+        uuid[6..8].reverse();
+        image[1024 + 128 + 16..1024 + 128 + 32].copy_from_slice(&uuid);
+
+        image
+    }
+
+    fn ext4_image(uuid: &str) -> Vec<u8> {
+        let mut image = vec![0; 4096];
+        image[1024 + 56..1024 + 58].copy_from_slice(&[0x53, 0xEF]);
+        image[1024 + 104..1024 + 120].copy_from_slice(&hex::decode(uuid.replace('-', "")).unwrap());
+
+        image
+    }
+
+    // Creates a fake `dev` and block class `sysfs` with the `disk`
+    // image and `partitions`, returning their paths.
+    fn fake_block(root: &Path, disk: &[u8], partitions: &[(&str, &[u8])]) -> (PathBuf, PathBuf) {
+        let dev = root.join("dev");
+        let sysfs = root.join("class/block");
+        fs::create_dir_all(&dev).unwrap();
+        fs::create_dir_all(&sysfs).unwrap();
+
+        let devices = root.join("devices/disk0");
+        fs::create_dir_all(&devices).unwrap();
+        fs::write(dev.join("disk0"), disk).unwrap();
+        symlink(&devices, sysfs.join("disk0")).unwrap();
+
+        for (i, (name, content)) in partitions.iter().enumerate() {
+            fs::create_dir(devices.join(name)).unwrap();
+            fs::write(devices.join(name).join("partition"), format!("{}\n", i + 1)).unwrap();
+            fs::write(dev.join(name), content).unwrap();
+            symlink(devices.join(name), sysfs.join(name)).unwrap();
+        }
+
+        (dev, sysfs)
+    }
+
+    #[test]
+    fn gpt_partitions() {
+        assert_eq!(
+            read_partitions(Cursor::new(gpt_image())).unwrap(),
+            vec![
+                Partition {
+                    number: 1,
+                    label: Some("boot".to_string()),
+                    uuid: "00000000-0000-0000-0000-000000000000".to_string(),
+                },
+                Partition {
+                    number: 2,
+                    label: Some("rootfs".to_string()),
+                    uuid: ROOTFS_UUID.to_string(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn invalid_gpt_header() {
+        let mut image = gpt_image();
+        image[512 + 84..512 + 88].copy_from_slice(&0u32.to_le_bytes());
+        let err = read_partitions(Cursor::new(&image)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        let mut image = gpt_image();
+        image[512 + 80..512 + 84].copy_from_slice(&u32::MAX.to_le_bytes());
+        let err = read_partitions(Cursor::new(&image)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        // Partition table beyond the end of the disk
+        assert!(read_partitions(Cursor::new(&gpt_image()[..1100])).is_err());
+    }
+
+    #[test]
+    fn mbr_partitions() {
+        let mut image = vec![0; 4096];
+        image[440..444].copy_from_slice(&0x1234_abcdu32.to_le_bytes());
+        image[446 + 16 + 4] = 0x83;
+        image[510..512].copy_from_slice(&[0x55, 0xAA]);
+
+        assert_eq!(
+            read_partitions(Cursor::new(image)).unwrap(),
+            vec![Partition { number: 2, label: None, uuid: "1234abcd-02".to_string() }]
+        );
+        assert!(read_partitions(Cursor::new(vec![0; 4096])).is_err());
+    }
+
+    #[test]
+    fn filesystem_uuids() {
+        assert_eq!(
+            filesystem_uuid(Cursor::new(ext4_image(ROOTFS_UUID))).unwrap(),
+            Some(ROOTFS_UUID.to_string())
+        );
+
+        let mut fat = vec![0; 4096];
+        fat[82..87].copy_from_slice(b"FAT32");
+        fat[67..71].copy_from_slice(&[0x78, 0x56, 0x34, 0x12]);
+        assert_eq!(filesystem_uuid(Cursor::new(fat)).unwrap(), Some("1234-5678".to_string()));
+
+        assert_eq!(filesystem_uuid(Cursor::new(vec![0; 128 * 1024])).unwrap(), None);
+    }
+
+    #[test]
+    fn scan_block_devices() {
+        let root = tempdir().unwrap();
+        let ext4 = ext4_image(ROOTFS_UUID);
+        let (dev, sysfs) =
+            fake_block(root.path(), &gpt_image(), &[("disk0p1", &[]), ("disk0p2", &ext4)]);
+
+        assert_eq!(
+            find_partition(&dev, &sysfs, |p| p.label == Some("rootfs".to_string())),
+            Some(dev.join("disk0p2"))
+        );
+        assert_eq!(
+            find_partition(&dev, &sysfs, |p| p.uuid == ROOTFS_UUID),
+            Some(dev.join("disk0p2"))
+        );
+        assert_eq!(find_partition(&dev, &sysfs, |p| p.label == Some("data".to_string())), None);
+        assert_eq!(
+            find_filesystem(&dev, &sysfs, &ROOTFS_UUID.to_uppercase()),
+            Some(dev.join("disk0p2"))
+        );
+    }
+
+    #[test]
+    fn udev_links_are_preferred() {
+        let root = tempdir().unwrap();
+        let (dev, sysfs) = fake_block(root.path(), &gpt_image(), &[("disk0p1", &[])]);
+        fs::create_dir_all(dev.join("disk/by-partlabel")).unwrap();
+        symlink(dev.join("disk0p1"), dev.join("disk/by-partlabel/rootfs")).unwrap();
+
+        assert_eq!(
+            find_device(&dev, &sysfs, "by-partlabel", "rootfs", |_, _| None),
+            Some(fs::canonicalize(dev.join("disk0p1")).unwrap())
+        );
+        assert_eq!(find_device(&dev, &sysfs, "by-partlabel", "boot", |_, _| None), None);
+    }
+}
@@ -2,45 +2,75 @@
 //
 // SPDX-License-Identifier: Apache-2.0
 
-use crate::utils::definitions::IdExt;
+use crate::utils::definitions::{IdExt, TargetTypeExt};
 use easy_process;
 use failure::format_err;
 use pkg_schema::definitions::{
     target_permissions::{Gid, Uid},
-    Filesystem,
+    Filesystem, TargetType,
+};
+use std::{
+    fs, io,
+    os::unix::{
+        fs::{FileTypeExt, MetadataExt, PermissionsExt},
+        io::AsRawFd,
+    },
+    path::Path,
 };
-use std::{io, path::Path};
 use sys_mount::{Mount, Unmount, UnmountDrop};
 
-pub(crate) fn find_compress_tarball_kind(
-    file: &Path,
-) -> Result<compress_tools::Kind, failure::Error> {
-    match infer::Infer::new()
-        .get_from_path(file)?
-        .ok_or_else(|| format_err!("Unknown type"))?
-        .ext
-        .as_str()
-    {
-        "bz2" => Ok(compress_tools::Kind::TarBZip2),
-        "gz" => Ok(compress_tools::Kind::TarGZip),
-        "lz" => Ok(compress_tools::Kind::TarLZip),
-        "xz" => Ok(compress_tools::Kind::TarXz),
-        "tar" => Ok(compress_tools::Kind::Tar),
+/// Formats supported for tarball objects.
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub(crate) enum TarballKind {
+    Tar,
+    Compressed(CompressKind),
+}
+
+pub(crate) fn find_compress_tarball_kind(file: &Path) -> Result<TarballKind, failure::Error> {
+    let mut infer = infer::Infer::new();
+    infer.add("application/zstd", "zst", is_zstd);
+
+    match infer.get_from_path(file)?.ok_or_else(|| format_err!("Unknown type"))?.ext.as_str() {
+        "bz2" => Ok(TarballKind::Compressed(CompressKind::BZip2)),
+        "gz" => Ok(TarballKind::Compressed(CompressKind::GZip)),
+        "lz" => Ok(TarballKind::Compressed(CompressKind::LZip)),
+        "xz" => Ok(TarballKind::Compressed(CompressKind::Xz)),
+        "zst" => Ok(TarballKind::Compressed(CompressKind::Zstd)),
+        "tar" => Ok(TarballKind::Tar),
         t => Err(format_err!("{} is not a valid archive type", t)),
     }
 }
 
-pub(crate) fn find_compress_kind(file: &Path) -> Result<compress_tools::Kind, failure::Error> {
-    match infer::Infer::new()
+/// Compression formats supported for single file objects.
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub(crate) enum CompressKind {
+    GZip,
+    BZip2,
+    Xz,
+    Zstd,
+    /// Decompressed by the external `lzip` tool
+    LZip,
+}
+
+fn is_zstd(buf: &[u8]) -> bool {
+    buf.len() > 3 && buf[0] == 0x28 && buf[1] == 0xB5 && buf[2] == 0x2F && buf[3] == 0xFD
+}
+
+pub(crate) fn find_compress_kind(file: &Path) -> Result<CompressKind, failure::Error> {
+    let mut infer = infer::Infer::new();
+    infer.add("application/zstd", "zst", is_zstd);
+
+    match infer
         .get_from_path(file)?
         .ok_or_else(|| format_err!("Unknown archive type"))?
         .ext
         .as_str()
     {
-        "gz" => Ok(compress_tools::Kind::GZip),
-        "bz2" => Ok(compress_tools::Kind::BZip2),
-        "xz" => Ok(compress_tools::Kind::Xz),
-        "lz" => Ok(compress_tools::Kind::LZip),
+        "gz" => Ok(CompressKind::GZip),
+        "bz2" => Ok(CompressKind::BZip2),
+        "xz" => Ok(CompressKind::Xz),
+        "zst" => Ok(CompressKind::Zstd),
+        "lz" => Ok(CompressKind::LZip),
         _ => Err(format_err!("Invalid archive type")),
     }
 }
@@ -76,39 +106,102 @@ pub(crate) fn format(
     Ok(())
 }
 
-pub(crate) fn mount_map<F>(
+/// Access to a device mounted by `mount_map` and `target_map`. Devices
+/// are only mounted writable when they are going to be changed, so
+/// checks leave them untouched.
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub(crate) enum MountMode {
+    ReadOnly,
+    ReadWrite,
+}
+
+pub(crate) fn mount_map<F, T>(
     source: &Path,
     fs: Filesystem,
     options: &str,
+    mode: MountMode,
     f: F,
-) -> Result<(), failure::Error>
+) -> Result<T, failure::Error>
 where
-    F: FnOnce(&Path) -> Result<(), failure::Error>,
+    F: FnOnce(&Path) -> Result<T, failure::Error>,
 {
     let tmpdir = tempfile::tempdir()?;
     let tmpdir = tmpdir.path();
 
     // We need to keep a guard otherwise it is dropped before the
     // closure is run.
-    let _guard = mount(source, &tmpdir, fs, options)?;
+    let _guard = mount(source, &tmpdir, fs, options, mode)?;
 
     f(tmpdir)
 }
 
+/// Runs `f` over the directory holding the content of `target`. Devices
+/// are mounted on a temporary directory, while `path` targets, which
+/// are already mounted, are used as is.
+pub(crate) fn target_map<F, T>(
+    target: &TargetType,
+    fs: Filesystem,
+    options: &str,
+    mode: MountMode,
+    f: F,
+) -> Result<T, failure::Error>
+where
+    F: FnOnce(&Path) -> Result<T, failure::Error>,
+{
+    match target {
+        TargetType::Path(path) => f(path),
+        _ => mount_map(&target.get_target()?, fs, options, mode, f),
+    }
+}
+
+/// Gets the root of the filesystem whose users and groups database
+/// resolves the names used on `target`, mapped at `path` by
+/// `target_map`. Directory targets are part of the running system, so
+/// its own database is used for them.
+pub(crate) fn users_root<'a>(target: &TargetType, path: &'a Path) -> &'a Path {
+    match target {
+        TargetType::Path(_) => Path::new("/"),
+        _ => path,
+    }
+}
+
 pub(crate) fn mount(
     source: &Path,
     dest: &Path,
     fs: Filesystem,
     options: &str,
+    mode: MountMode,
 ) -> io::Result<UnmountDrop<Mount>> {
-    Ok(Mount::new(
-        source,
-        dest,
-        format!("{}", fs).as_str(),
-        sys_mount::MountFlags::empty(),
-        Some(options),
-    )?
-    .into_unmount_drop(sys_mount::UnmountFlags::DETACH))
+    let flags = match mode {
+        MountMode::ReadOnly => sys_mount::MountFlags::RDONLY,
+        MountMode::ReadWrite => sys_mount::MountFlags::empty(),
+    };
+
+    Ok(Mount::new(source, dest, format!("{}", fs).as_str(), flags, Some(options))?
+        .into_unmount_drop(sys_mount::UnmountFlags::DETACH))
+}
+
+/// Returns the space, in bytes, available for unprivileged users on
+/// the filesystem containing `path`.
+pub(crate) fn available_space(path: &Path) -> Result<u64, failure::Error> {
+    let stat = nix::sys::statvfs::statvfs(path)?;
+    Ok(stat.blocks_available() as u64 * stat.fragment_size() as u64)
+}
+
+/// Returns the size, in bytes, of the block device at `path` or `None`
+/// when it is not a block device, as regular files grow on demand.
+pub(crate) fn block_device_size(path: &Path) -> Result<Option<u64>, failure::Error> {
+    // From https://github.com/torvalds/linux/blob/master/include/uapi/linux/fs.h
+    nix::ioctl_read!(blkgetsize64, 0x12, 114, u64);
+
+    let device = fs::File::open(path)?;
+    if !device.metadata()?.file_type().is_block_device() {
+        return Ok(None);
+    }
+
+    let mut size = 0;
+    unsafe { blkgetsize64(device.as_raw_fd(), &mut size)? };
+    Ok(Some(size))
 }
 
 pub(crate) fn chmod(path: &Path, mode: u32) -> Result<(), failure::Error> {
@@ -122,10 +215,120 @@ pub(crate) fn chmod(path: &Path, mode: u32) -> Result<(), failure::Error> {
     Ok(())
 }
 
-pub(crate) fn chown(path: &Path, uid: &Option<Uid>, gid: &Option<Gid>) -> nix::Result<()> {
+/// Changes the owner of `path`. User and group names are looked up on
+/// the filesystem mounted at `root`, which is the one being installed.
+pub(crate) fn chown(
+    path: &Path,
+    uid: &Option<Uid>,
+    gid: &Option<Gid>,
+    root: &Path,
+) -> Result<(), failure::Error> {
     nix::unistd::chown(
         path,
-        uid.as_ref().map(|id| nix::unistd::Uid::from_raw(id.as_u32())),
-        gid.as_ref().map(|id| nix::unistd::Gid::from_raw(id.as_u32())),
-    )
+        uid.as_ref().map(|id| id.resolve(root)).transpose()?.map(nix::unistd::Uid::from_raw),
+        gid.as_ref().map(|id| id.resolve(root)).transpose()?.map(nix::unistd::Gid::from_raw),
+    )?;
+
+    Ok(())
+}
+
+/// Replaces `dest` with the content written by `write`, so a power cut
+/// never leaves it half-written. The content goes into a temporary
+/// file in the same directory, which is synced and renamed over
+/// `dest`, and the directory is synced so the rename itself is
+/// durable. Missing parent directories are created and the mode and
+/// owner of a replaced `dest` are kept. `write` also gets the path of
+/// the temporary file to adjust its permissions before the rename.
+pub(crate) fn replace_file<F>(dest: &Path, write: F) -> Result<(), failure::Error>
+where
+    F: FnOnce(&fs::File, &Path) -> Result<(), failure::Error>,
+{
+    let dir = dest.parent().ok_or_else(|| format_err!("{:?} has no parent directory", dest))?;
+    fs::create_dir_all(dir)?;
+
+    let file = tempfile::Builder::new().prefix(".updatehub-").tempfile_in(dir)?;
+    match dest.metadata() {
+        Ok(metadata) => {
+            file.as_file().set_permissions(metadata.permissions())?;
+            nix::unistd::chown(
+                file.path(),
+                Some(nix::unistd::Uid::from_raw(metadata.uid())),
+                Some(nix::unistd::Gid::from_raw(metadata.gid())),
+            )?;
+        }
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => {
+            file.as_file().set_permissions(fs::Permissions::from_mode(0o644))?;
+        }
+        Err(e) => return Err(e.into()),
+    }
+
+    write(file.as_file(), file.path())?;
+    file.as_file().sync_all()?;
+    file.persist(dest)?;
+    fs::File::open(dir)?.sync_all()?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use std::io::Write;
+    use tempfile::tempdir;
+
+    #[test]
+    fn lzip_compressed_kind() {
+        let dir = tempdir().unwrap();
+        let file = dir.path().join("file.lz");
+        fs::write(&file, b"LZIP\x01\x0c\x00\x00\x00\x00\x00\x00").unwrap();
+
+        assert_eq!(find_compress_kind(&file).unwrap(), CompressKind::LZip);
+        assert_eq!(
+            find_compress_tarball_kind(&file).unwrap(),
+            TarballKind::Compressed(CompressKind::LZip)
+        );
+    }
+
+    #[test]
+    fn replace_existing_file() {
+        let dir = tempdir().unwrap();
+        let dest = dir.path().join("file");
+        fs::write(&dest, b"original content").unwrap();
+        fs::set_permissions(&dest, fs::Permissions::from_mode(0o600)).unwrap();
+
+        replace_file(&dest, |mut file, _| Ok(file.write_all(b"new")?)).unwrap();
+        assert_eq!(fs::read(&dest).unwrap(), b"new");
+        assert_eq!(dest.metadata().unwrap().mode() % 0o1000, 0o600);
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn replace_missing_file() {
+        let dir = tempdir().unwrap();
+        let dest = dir.path().join("missing/parent/file");
+
+        replace_file(&dest, |mut file, path| {
+            file.write_all(b"new")?;
+            chmod(path, 0o640)
+        })
+        .unwrap();
+        assert_eq!(fs::read(&dest).unwrap(), b"new");
+        assert_eq!(dest.metadata().unwrap().mode() % 0o1000, 0o640);
+    }
+
+    #[test]
+    fn failed_write_keeps_file() {
+        let dir = tempdir().unwrap();
+        let dest = dir.path().join("file");
+        fs::write(&dest, b"original content").unwrap();
+
+        assert!(replace_file(&dest, |mut file, _| {
+            file.write_all(b"partial")?;
+            Err(format_err!("write failed"))
+        })
+        .is_err());
+        assert_eq!(fs::read(&dest).unwrap(), b"original content");
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
 }
@@ -2,9 +2,13 @@
 //
 // SPDX-License-Identifier: Apache-2.0
 
+use crate::utils::{self, fs::CompressKind};
 use std::{
-    io::{BufReader, BufWriter, Read, Seek, Write},
+    fs,
+    io::{self, BufRead, BufReader, BufWriter, Read, Seek, SeekFrom, Write},
     os::unix::io::AsRawFd,
+    path::Path,
+    process::{Child, Command, Stdio},
     time::Duration,
 };
 pub(crate) use timeout_readwrite::{TimeoutReader, TimeoutWriter};
@@ -22,3 +26,160 @@ where
 {
     BufWriter::with_capacity(chunk_size, TimeoutWriter::new(writer, Duration::from_secs(5)))
 }
+
+/// Gets a reader for the uncompressed content of `source`. The data is
+/// decompressed while read so the whole content is never held in
+/// memory.
+pub(crate) fn uncompressed_reader(source: &Path) -> Result<Box<dyn Read>, failure::Error> {
+    Ok(match utils::fs::find_compress_kind(source)? {
+        CompressKind::LZip => Box::new(ProcessReader(
+            Command::new("lzip").arg("-dc").arg(source).stdout(Stdio::piped()).spawn()?,
+        )),
+        kind => decompressor(kind, BufReader::new(fs::File::open(source)?))?,
+    })
+}
+
+/// Wraps `input` with the decoder for the `kind` compression format.
+/// LZip content is only supported through `uncompressed_reader` as its
+/// decoder runs as an external process.
+pub(crate) fn decompressor<'a, R>(kind: CompressKind, input: R) -> io::Result<Box<dyn Read + 'a>>
+where
+    R: BufRead + 'a,
+{
+    Ok(match kind {
+        CompressKind::GZip => Box::new(flate2::bufread::MultiGzDecoder::new(input)),
+        CompressKind::BZip2 => Box::new(bzip2::bufread::BzDecoder::new(input)),
+        CompressKind::Xz => Box::new(xz2::bufread::XzDecoder::new(input)),
+        CompressKind::Zstd => Box::new(zstd::Decoder::with_buffer(input)?),
+        CompressKind::LZip => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "LZip content can only be read from a file",
+            ))
+        }
+    })
+}
+
+// Reads the output of a decompression process, failing at the end of
+// the stream if it has not exited successfully.
+struct ProcessReader(Child);
+
+impl Read for ProcessReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.0.stdout.as_mut().expect("stdout is piped").read(buf)?;
+        if read == 0 && !buf.is_empty() {
+            let status = self.0.wait()?;
+            if !status.success() {
+                return Err(io::Error::other(format!(
+                    "Decompression exited with error: {}",
+                    status
+                )));
+            }
+        }
+
+        Ok(read)
+    }
+}
+
+/// Copies up to `count` blocks of `chunk_size` bytes from `input` to
+/// `output`, stopping early when EOF is reached.
+pub(crate) fn copy_chunks<R, W, I>(
+    chunk_size: usize,
+    count: I,
+    input: &mut R,
+    output: &mut W,
+) -> io::Result<()>
+where
+    R: Read,
+    W: Write,
+    I: Iterator,
+{
+    let mut buf = Vec::with_capacity(chunk_size);
+    for _ in count {
+        buf.clear();
+        input.by_ref().take(chunk_size as u64).read_to_end(&mut buf)?;
+
+        // We break the loop in case we have no bytes left for read
+        // (EOF is reached).
+        if buf.is_empty() {
+            break;
+        }
+
+        output.write_all(&buf)?;
+    }
+
+    Ok(())
+}
+
+/// Copies up to `count` blocks of `chunk_size` bytes from `input` to
+/// `output`, like `copy_chunks`, but reads each block back from
+/// `output` first and only writes the ones which differ. Returns the
+/// number of bytes written.
+pub(crate) fn copy_changed_chunks<R, F, I>(
+    chunk_size: usize,
+    count: I,
+    input: &mut R,
+    output: &mut F,
+) -> io::Result<u64>
+where
+    R: Read,
+    F: Read + Write + Seek,
+    I: Iterator,
+{
+    let mut buf = Vec::with_capacity(chunk_size);
+    let mut current = Vec::with_capacity(chunk_size);
+    let mut written = 0;
+    for _ in count {
+        buf.clear();
+        input.by_ref().take(chunk_size as u64).read_to_end(&mut buf)?;
+        if buf.is_empty() {
+            break;
+        }
+
+        current.clear();
+        Read::by_ref(output).take(buf.len() as u64).read_to_end(&mut current)?;
+        if current == buf {
+            continue;
+        }
+
+        output.seek(SeekFrom::Current(-(current.len() as i64)))?;
+        output.write_all(&buf)?;
+        written += buf.len() as u64;
+    }
+
+    Ok(written)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use std::iter;
+    use tempfile::tempfile;
+
+    #[test]
+    fn copy_only_changed_chunks() {
+        let content = (0..4096).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+        let mut target = tempfile().unwrap();
+        let mut old = content.clone();
+        old[1000] = 0xFF;
+        old[3000..3010].iter_mut().for_each(|b| *b = 0);
+        old.truncate(3500);
+        target.write_all(&old).unwrap();
+
+        target.seek(SeekFrom::Start(0)).unwrap();
+        let written =
+            copy_changed_chunks(512, iter::repeat(()), &mut &content[..], &mut target).unwrap();
+        assert_eq!(written, 4 * 512);
+
+        target.seek(SeekFrom::Start(0)).unwrap();
+        let written =
+            copy_changed_chunks(512, iter::repeat(()), &mut &content[..], &mut target).unwrap();
+        assert_eq!(written, 0);
+
+        let mut result = Vec::new();
+        target.seek(SeekFrom::Start(0)).unwrap();
+        target.read_to_end(&mut result).unwrap();
+        assert_eq!(result, content);
+    }
+}
@@ -0,0 +1,357 @@
+// Copyright (C) 2019 O.S. Systems Sofware LTDA
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use failure::{ensure, format_err};
+use serde::de::DeserializeOwned;
+use serde_cbor::Value;
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::{self, BufRead, BufReader, Read, Write},
+    os::unix::io::AsRawFd,
+    time::Duration,
+};
+use timeout_readwrite::TimeoutReader;
+
+const OP_READ: u8 = 0;
+const OP_READ_RSP: u8 = 1;
+const OP_WRITE: u8 = 2;
+const OP_WRITE_RSP: u8 = 3;
+
+const GROUP_OS: u16 = 0;
+const GROUP_IMAGE: u16 = 1;
+const ID_OS_RESET: u8 = 5;
+const ID_IMAGE_STATE: u8 = 0;
+const ID_IMAGE_UPLOAD: u8 = 1;
+
+const HEADER_SIZE: usize = 8;
+const FRAME_START: &[u8] = &[0x06, 0x09];
+const FRAME_CONTINUATION: &[u8] = &[0x04, 0x14];
+// Frames are limited to 127 bytes, including the marker and the
+// newline.
+const FRAME_BODY_SIZE: usize = 124;
+// Amount of image data sent on each upload request, small enough to
+// fit the default SMP buffers of Zephyr.
+const UPLOAD_CHUNK_SIZE: usize = 128;
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Minimal client for the mcumgr Simple Management Protocol (SMP) over
+/// a serial port, as implemented by Zephyr's device management
+/// subsystem.
+pub(crate) struct Client<R, W> {
+    reader: BufReader<R>,
+    writer: W,
+    seq: u8,
+}
+
+impl Client<TimeoutReader<fs::File>, fs::File> {
+    /// Opens the serial `port` configuring it in raw mode at 115200
+    /// bauds, the default used by mcumgr.
+    pub(crate) fn open(port: &std::path::Path) -> Result<Self, failure::Error> {
+        use nix::sys::termios;
+
+        let port = fs::OpenOptions::new().read(true).write(true).open(port)?;
+        let mut attrs = termios::tcgetattr(port.as_raw_fd())?;
+        termios::cfmakeraw(&mut attrs);
+        termios::cfsetspeed(&mut attrs, termios::BaudRate::B115200)?;
+        termios::tcsetattr(port.as_raw_fd(), termios::SetArg::TCSANOW, &attrs)?;
+
+        Ok(Self::new(TimeoutReader::new(port.try_clone()?, RESPONSE_TIMEOUT), port))
+    }
+}
+
+impl<R: Read, W: Write> Client<R, W> {
+    pub(crate) fn new(reader: R, writer: W) -> Self {
+        Self { reader: BufReader::new(reader), writer, seq: 0 }
+    }
+
+    /// Uploads `image` into the secondary slot of the device.
+    pub(crate) fn upload_image(&mut self, image: &[u8]) -> Result<(), failure::Error> {
+        let mut off = 0;
+
+        while off < image.len() {
+            let end = image.len().min(off + UPLOAD_CHUNK_SIZE);
+            let mut request = BTreeMap::new();
+            request.insert(text("off"), Value::Integer(off as i128));
+            request.insert(text("data"), Value::Bytes(image[off..end].to_vec()));
+            if off == 0 {
+                request.insert(text("len"), Value::Integer(image.len() as i128));
+            }
+
+            // The device replies with the offset it expects next, which
+            // allows it to resume interrupted uploads.
+            let response = self.request(OP_WRITE, GROUP_IMAGE, ID_IMAGE_UPLOAD, request)?;
+            let next = integer(&response, "off")
+                .ok_or_else(|| format_err!("Image upload response has no offset"))?
+                as usize;
+            ensure!(next != off && next <= image.len(), "Invalid image upload offset: {}", next);
+            off = next;
+        }
+
+        Ok(())
+    }
+
+    /// Marks the image in the secondary slot to be tested on the next
+    /// boot.
+    pub(crate) fn test_image(&mut self) -> Result<(), failure::Error> {
+        let state = self.request(OP_READ, GROUP_IMAGE, ID_IMAGE_STATE, BTreeMap::new())?;
+        let hash = field(&state, "images")
+            .and_then(|images| match images {
+                Value::Array(images) => images
+                    .iter()
+                    .filter_map(|image| match image {
+                        Value::Map(image) => Some(image),
+                        _ => None,
+                    })
+                    .find(|image| integer(image, "slot") == Some(1)),
+                _ => None,
+            })
+            .and_then(|image| field(image, "hash"))
+            .ok_or_else(|| format_err!("No image found in the secondary slot"))?
+            .clone();
+
+        let mut request = BTreeMap::new();
+        request.insert(text("hash"), hash);
+        request.insert(text("confirm"), Value::Bool(false));
+        self.request(OP_WRITE, GROUP_IMAGE, ID_IMAGE_STATE, request)?;
+
+        Ok(())
+    }
+
+    /// Requests the device to reset, so it boots the new image.
+    pub(crate) fn reset(&mut self) -> Result<(), failure::Error> {
+        self.request(OP_WRITE, GROUP_OS, ID_OS_RESET, BTreeMap::new())?;
+        Ok(())
+    }
+
+    fn request(
+        &mut self,
+        op: u8,
+        group: u16,
+        id: u8,
+        payload: BTreeMap<Value, Value>,
+    ) -> Result<BTreeMap<Value, Value>, failure::Error> {
+        let seq = self.seq;
+        self.seq = self.seq.wrapping_add(1);
+        write_packet(&mut self.writer, &encode(op, group, seq, id, &Value::Map(payload))?)?;
+
+        loop {
+            let packet = read_packet(&mut self.reader)?;
+            let (header, response) = decode::<BTreeMap<Value, Value>>(&packet)?;
+
+            // Responses of previous, timed out, requests are ignored.
+            if header.seq != seq {
+                continue;
+            }
+
+            ensure!(
+                header.op == op + 1 && header.group == group && header.id == id,
+                "Unexpected response from device"
+            );
+            let rc = integer(&response, "rc").unwrap_or(0);
+            ensure!(rc == 0, "Device failed to handle request with error code {}", rc);
+
+            return Ok(response);
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub(crate) struct Header {
+    pub(crate) op: u8,
+    pub(crate) group: u16,
+    pub(crate) seq: u8,
+    pub(crate) id: u8,
+}
+
+pub(crate) fn encode(
+    op: u8,
+    group: u16,
+    seq: u8,
+    id: u8,
+    payload: &Value,
+) -> Result<Vec<u8>, failure::Error> {
+    let payload = serde_cbor::to_vec(payload)?;
+    let mut packet = Vec::with_capacity(HEADER_SIZE + payload.len());
+    packet.extend(&[op, 0]);
+    packet.extend(&(payload.len() as u16).to_be_bytes());
+    packet.extend(&group.to_be_bytes());
+    packet.extend(&[seq, id]);
+    packet.extend(payload);
+
+    Ok(packet)
+}
+
+pub(crate) fn decode<T: DeserializeOwned>(packet: &[u8]) -> Result<(Header, T), failure::Error> {
+    ensure!(packet.len() >= HEADER_SIZE, "Packet too short");
+    let header = Header {
+        op: packet[0] & 0x07,
+        group: u16::from_be_bytes([packet[4], packet[5]]),
+        seq: packet[6],
+        id: packet[7],
+    };
+
+    Ok((header, serde_cbor::from_slice(&packet[HEADER_SIZE..])?))
+}
+
+/// Writes `packet` framed for the serial transport: prefixed by its
+/// length, followed by its CRC16 and base64 encoded in lines.
+pub(crate) fn write_packet<W: Write>(writer: &mut W, packet: &[u8]) -> io::Result<()> {
+    let mut buf = Vec::with_capacity(packet.len() + 4);
+    buf.extend(&(packet.len() as u16 + 2).to_be_bytes());
+    buf.extend(packet);
+    buf.extend(&crc16(packet).to_be_bytes());
+
+    let encoded = base64::encode(&buf);
+    for (i, body) in encoded.as_bytes().chunks(FRAME_BODY_SIZE).enumerate() {
+        writer.write_all(if i == 0 { FRAME_START } else { FRAME_CONTINUATION })?;
+        writer.write_all(body)?;
+        writer.write_all(b"\n")?;
+    }
+
+    writer.flush()
+}
+
+/// Reads the next packet from the serial transport, skipping any line
+/// which is not part of a frame, such as console output.
+pub(crate) fn read_packet<R: BufRead>(reader: &mut R) -> Result<Vec<u8>, failure::Error> {
+    let mut encoded = Vec::new();
+
+    loop {
+        let mut line = Vec::new();
+        if reader.read_until(b'\n', &mut line)? == 0 {
+            return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+        }
+        while line.last().map(|c| c.is_ascii_whitespace()).unwrap_or(false) {
+            line.pop();
+        }
+
+        if line.starts_with(FRAME_START) {
+            encoded = line[FRAME_START.len()..].to_vec();
+        } else if line.starts_with(FRAME_CONTINUATION) && !encoded.is_empty() {
+            encoded.extend(&line[FRAME_CONTINUATION.len()..]);
+        } else {
+            continue;
+        }
+
+        let buf = base64::decode(&encoded)?;
+        if buf.len() < 2 || buf.len() < u16::from_be_bytes([buf[0], buf[1]]) as usize + 2 {
+            continue;
+        }
+
+        let len = u16::from_be_bytes([buf[0], buf[1]]) as usize;
+
+        ensure!(len >= 2 && buf.len() == len + 2, "Invalid frame length");
+        let (packet, crc) = buf[2..].split_at(len - 2);
+        ensure!(crc16(packet).to_be_bytes() == crc, "Frame CRC mismatch");
+
+        return Ok(packet.to_vec());
+    }
+}
+
+// CRC16-CCITT, with 0x1021 polynomial and no initial value.
+fn crc16(data: &[u8]) -> u16 {
+    data.iter().fold(0, |crc, byte| {
+        (0..8).fold(crc ^ (u16::from(*byte) << 8), |crc, _| {
+            if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x1021
+            } else {
+                crc << 1
+            }
+        })
+    })
+}
+
+fn text(s: &str) -> Value {
+    Value::Text(s.to_string())
+}
+
+fn field<'a>(map: &'a BTreeMap<Value, Value>, key: &str) -> Option<&'a Value> {
+    map.get(&text(key))
+}
+
+fn integer(map: &BTreeMap<Value, Value>, key: &str) -> Option<i128> {
+    match field(map, key)? {
+        Value::Integer(n) => Some(*n),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    /// Fake mcumgr device which handles requests until it is reset,
+    /// returning the uploaded image.
+    pub(crate) fn fake_device<R: Read, W: Write>(reader: R, mut writer: W) -> Vec<u8> {
+        let mut reader = BufReader::new(reader);
+        let mut image = Vec::new();
+
+        loop {
+            let packet = read_packet(&mut reader).unwrap();
+            let (header, request) = decode::<BTreeMap<Value, Value>>(&packet).unwrap();
+            let mut response = BTreeMap::new();
+            response.insert(text("rc"), Value::Integer(0));
+
+            match (header.group, header.id, header.op) {
+                (GROUP_IMAGE, ID_IMAGE_UPLOAD, OP_WRITE) => {
+                    if let Some(Value::Bytes(data)) = field(&request, "data") {
+                        image.extend(data);
+                    }
+                    response.insert(text("off"), Value::Integer(image.len() as i128));
+                }
+                (GROUP_IMAGE, ID_IMAGE_STATE, op) => {
+                    if op == OP_WRITE {
+                        assert_eq!(field(&request, "hash"), Some(&Value::Bytes(vec![1; 32])));
+                    }
+                    let slot = |n, hash| {
+                        let mut image = BTreeMap::new();
+                        image.insert(text("slot"), Value::Integer(n));
+                        image.insert(text("hash"), Value::Bytes(hash));
+                        Value::Map(image)
+                    };
+                    response.insert(
+                        text("images"),
+                        Value::Array(vec![slot(0, vec![0; 32]), slot(1, vec![1; 32])]),
+                    );
+                }
+                (GROUP_OS, ID_OS_RESET, OP_WRITE) => {}
+                _ => panic!("Unexpected request: {:?}", header),
+            }
+
+            let op = if header.op == OP_READ { OP_READ_RSP } else { OP_WRITE_RSP };
+            let packet = encode(op, header.group, header.seq, header.id, &Value::Map(response));
+            write_packet(&mut writer, &packet.unwrap()).unwrap();
+
+            if header.group == GROUP_OS {
+                return image;
+            }
+        }
+    }
+
+    #[test]
+    fn crc() {
+        assert_eq!(crc16(b"123456789"), 0x31C3);
+    }
+
+    #[test]
+    fn frames_roundtrip() {
+        let packet = (0..=255).collect::<Vec<u8>>();
+        let mut buf = b"console output\n".to_vec();
+        write_packet(&mut buf, &packet).unwrap();
+
+        assert!(buf.split(|c| *c == b'\n').all(|line| line.len() < 127));
+        assert_eq!(read_packet(&mut &buf[..]).unwrap(), packet);
+    }
+
+    #[test]
+    fn corrupted_frame() {
+        let mut buf = Vec::new();
+        write_packet(&mut buf, b"some packet").unwrap();
+        buf[4] = if buf[4] == b'A' { b'B' } else { b'A' };
+
+        assert!(read_packet(&mut &buf[..]).is_err());
+    }
+}
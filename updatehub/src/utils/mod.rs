@@ -3,6 +3,10 @@
 // SPDX-License-Identifier: Apache-2.0
 
 pub(crate) mod definitions;
+pub(crate) mod disk;
 pub(crate) mod fs;
 pub(crate) mod io;
+pub(crate) mod mcumgr;
 pub(crate) mod mtd;
+pub(crate) mod sparse;
+pub(crate) mod xattr;
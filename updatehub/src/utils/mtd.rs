@@ -2,44 +2,77 @@
 //
 // SPDX-License-Identifier: Apache-2.0
 
-use failure::format_err;
+use failure::{ensure, format_err};
+use slog_scope::info;
 use std::{
     fs,
-    io::{BufRead, BufReader},
-    path::PathBuf,
+    io::{self, BufRead, BufReader, Read, Write},
+    os::unix::fs::FileExt,
+    path::{Path, PathBuf},
 };
 
-pub(crate) use ffi::is_nand;
+const UBI_SYSFS: &str = "/sys/class/ubi";
 
 pub(crate) fn target_device_from_ubi_volume_name(volume: &str) -> Result<PathBuf, failure::Error> {
-    let re = regex::Regex::new(r"^Volume ID:   (?P<volume>\d+) \(on ubi(\d+)\)$").unwrap();
-    walkdir::WalkDir::new("/dev")
-        .min_depth(1)
-        .into_iter()
-        .filter_entry(|p| {
-            p.file_name()
-                .to_str()
-                .map(|n| n.starts_with("ubi") && !n.contains('_'))
-                .unwrap_or(false)
-        })
+    ubi_volume_from_sysfs(Path::new(UBI_SYSFS), volume)
+}
+
+// Looks for the volume named `volume` in the UBI sysfs class at
+// `sysfs`, where each volume has a `ubiX_Y` entry holding its name.
+fn ubi_volume_from_sysfs(sysfs: &Path, volume: &str) -> Result<PathBuf, failure::Error> {
+    fs::read_dir(sysfs)?
         .filter_map(Result::ok)
-        .find_map(|entry| {
-            let path = entry.path();
-            let output =
-                easy_process::run(&format!("ubinfo {} -N {}", path.display(), volume)).ok()?;
-
-            let line = output.stdout.lines().next()?;
-            let re_match = re.captures(line)?;
-
-            Some(PathBuf::from(format!(
-                "{}_{}",
-                path.display(),
-                &re_match.name("volume").unwrap().as_str()
-            )))
+        .map(|entry| entry.file_name())
+        .filter(|entry| entry.to_str().map(|n| n.contains('_')).unwrap_or(false))
+        .find(|entry| {
+            fs::read_to_string(sysfs.join(entry).join("name"))
+                .map(|name| name.trim_end_matches('\n') == volume)
+                .unwrap_or(false)
         })
+        .map(|entry| Path::new("/dev").join(entry))
         .ok_or_else(|| format_err!("Unable to find Ubi Volume"))
 }
 
+/// Returns the usable size, in bytes, of the UBI volume `device` as
+/// reported by sysfs.
+pub(crate) fn ubi_volume_size(device: &Path) -> Result<u64, failure::Error> {
+    let volume = device
+        .file_name()
+        .ok_or_else(|| format_err!("Invalid Ubi Volume device: {}", device.display()))?;
+
+    Ok(fs::read_to_string(Path::new(UBI_SYSFS).join(volume).join("data_bytes"))?.trim().parse()?)
+}
+
+/// Replaces the content of the UBI volume `device` with `size` bytes
+/// read from `data`.
+pub(crate) fn update_ubi_volume<R: Read>(
+    device: &Path,
+    size: u64,
+    data: R,
+) -> Result<(), failure::Error> {
+    let mut volume = fs::OpenOptions::new().write(true).open(device)?;
+    ffi::start_volume_update(&volume, size)
+        .map_err(|e| format_err!("Unable to start update of {:?}: {}", device, e))?;
+    write_volume(&mut volume, size, data)?;
+    volume.sync_all()?;
+
+    Ok(())
+}
+
+// The update is only committed by the kernel once exactly the announced
+// `size` is written, so a source of any other size is an error.
+fn write_volume<W: Write, R: Read>(
+    volume: &mut W,
+    size: u64,
+    mut data: R,
+) -> Result<(), failure::Error> {
+    let written = io::copy(&mut data.by_ref().take(size), volume)?;
+    ensure!(written == size, "Source has {} bytes, expected {}", written, size);
+    ensure!(data.read(&mut [0])? == 0, "Source is larger than {} bytes", size);
+
+    Ok(())
+}
+
 pub(crate) fn target_device_from_mtd_name(name: &str) -> Result<PathBuf, failure::Error> {
     let re =
         regex::Regex::new(r#"^(?P<dev>mtd\d): ([[:xdigit:]]+) ([[:xdigit:]]+) "(?P<name>.*)"$"#)
@@ -59,15 +92,164 @@ pub(crate) fn target_device_from_mtd_name(name: &str) -> Result<PathBuf, failure
         .ok_or_else(|| format_err!("Unable to find match for mtd device: {}", name))
 }
 
+/// Geometry of a MTD device.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct MtdInfo {
+    pub(crate) size: u64,
+    pub(crate) erase_size: u64,
+    pub(crate) write_size: u64,
+    pub(crate) is_nand: bool,
+}
+
+/// Operations used to write into a MTD device.
+pub(crate) trait Mtd {
+    fn info(&self) -> MtdInfo;
+    fn is_bad_block(&self, offset: u64) -> io::Result<bool>;
+    fn erase_block(&mut self, offset: u64) -> io::Result<()>;
+    fn write_at(&mut self, offset: u64, data: &[u8]) -> io::Result<()>;
+    fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> io::Result<()>;
+}
+
+/// MTD character device, such as `/dev/mtd0`.
+pub(crate) struct MtdDevice {
+    file: fs::File,
+    info: MtdInfo,
+}
+
+impl MtdDevice {
+    pub(crate) fn open(device: &Path) -> Result<Self, failure::Error> {
+        let file = fs::OpenOptions::new().read(true).write(true).open(device)?;
+        let info = ffi::get_info(&file)
+            .map_err(|e| format_err!("Unable to get MTD info of {:?}: {}", device, e))?;
+
+        Ok(Self { file, info })
+    }
+}
+
+impl Mtd for MtdDevice {
+    fn info(&self) -> MtdInfo {
+        self.info
+    }
+
+    fn is_bad_block(&self, offset: u64) -> io::Result<bool> {
+        ffi::is_bad_block(&self.file, offset)
+    }
+
+    fn erase_block(&mut self, offset: u64) -> io::Result<()> {
+        ffi::erase(&self.file, offset, self.info.erase_size)
+    }
+
+    fn write_at(&mut self, offset: u64, data: &[u8]) -> io::Result<()> {
+        self.file.write_all_at(data, offset)
+    }
+
+    fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> io::Result<()> {
+        self.file.read_exact_at(buf, offset)
+    }
+}
+
+pub(crate) fn is_nand(device: &Path) -> Result<bool, failure::Error> {
+    Ok(MtdDevice::open(device)?.info().is_nand)
+}
+
+/// Erases the whole `mtd` device and writes `image` from its start,
+/// skipping bad blocks on NAND devices. Each written block is read back
+/// to verify it.
+pub(crate) fn write_image<M: Mtd, R: Read>(
+    mtd: &mut M,
+    mut image: R,
+) -> Result<(), failure::Error> {
+    let info = mtd.info();
+    let erase_size = info.erase_size as usize;
+    let mut block = Vec::with_capacity(erase_size);
+    let mut readback = vec![0; erase_size];
+    let mut written = false;
+
+    for offset in (0..info.size).step_by(erase_size) {
+        if info.is_nand && mtd.is_bad_block(offset)? {
+            info!("Skipping bad block at {:#x}", offset);
+            continue;
+        }
+
+        mtd.erase_block(offset)
+            .map_err(|e| format_err!("Failed to erase block at {:#x}: {}", offset, e))?;
+        if written {
+            continue;
+        }
+
+        block.clear();
+        image.by_ref().take(info.erase_size).read_to_end(&mut block)?;
+        if block.is_empty() {
+            written = true;
+            continue;
+        }
+
+        // Flash is written by pages, so the last one is padded with the
+        // erased state.
+        let write_size = info.write_size.max(1) as usize;
+        let padding = (write_size - block.len() % write_size) % write_size;
+        block.resize(block.len() + padding, 0xFF);
+
+        mtd.write_at(offset, &block)
+            .map_err(|e| format_err!("Failed to write block at {:#x}: {}", offset, e))?;
+        mtd.read_at(offset, &mut readback[..block.len()])?;
+        ensure!(readback[..block.len()] == block[..], "Verification failed at {:#x}", offset);
+    }
+
+    ensure!(
+        written || image.read(&mut [0])? == 0,
+        "Image is larger than the available space on the device"
+    );
+
+    Ok(())
+}
+
+/// Reads back the `mtd` device, skipping bad blocks on NAND devices,
+/// ensuring it holds `image` from its start.
+pub(crate) fn verify_image<M: Mtd, R: Read>(
+    mtd: &mut M,
+    mut image: R,
+) -> Result<(), failure::Error> {
+    let info = mtd.info();
+    let mut block = Vec::with_capacity(info.erase_size as usize);
+    let mut readback = vec![0; info.erase_size as usize];
+
+    for offset in (0..info.size).step_by(info.erase_size as usize) {
+        if info.is_nand && mtd.is_bad_block(offset)? {
+            continue;
+        }
+
+        block.clear();
+        image.by_ref().take(info.erase_size).read_to_end(&mut block)?;
+        if block.is_empty() {
+            return Ok(());
+        }
+
+        mtd.read_at(offset, &mut readback[..block.len()])?;
+        ensure!(readback[..block.len()] == block[..], "Verification failed at {:#x}", offset);
+    }
+
+    ensure!(image.read(&mut [0])? == 0, "Image is larger than the device");
+
+    Ok(())
+}
+
 mod ffi {
-    use nix::ioctl_read;
-    use std::{mem::MaybeUninit, os::unix::io::AsRawFd, path::Path};
+    use super::MtdInfo;
+    use nix::{ioctl_read, ioctl_write_ptr};
+    use std::{fs::File, io, mem::MaybeUninit, os::unix::io::AsRawFd};
 
     // From https://github.com/torvalds/linux/blob/master/include/uapi/mtd/mtd-abi.h
     const MTD_NANDFLASH: u8 = 4;
     const MTD_MLCNANDFLASH: u8 = 8;
-    const MEMGETINFO: u8 = b'M';
+    const MTD_IOC_MAGIC: u8 = b'M';
     const MEMGETINFO_MODE: u8 = 1;
+    const MEMERASE_MODE: u8 = 2;
+    const MEMGETBADBLOCK_MODE: u8 = 11;
+
+    // From https://github.com/torvalds/linux/blob/master/include/uapi/mtd/ubi-user.h
+    const UBI_VOL_IOC_MAGIC: u8 = b'O';
+    const UBI_IOCVOLUP_MODE: u8 = 0;
 
     #[repr(C)]
     pub struct mtd_info_user {
@@ -80,17 +262,51 @@ mod ffi {
         padding: u64,
     }
 
-    ioctl_read!(mtd_get_info, MEMGETINFO, MEMGETINFO_MODE, mtd_info_user);
+    #[repr(C)]
+    pub struct erase_info_user {
+        start: u32,
+        length: u32,
+    }
+
+    ioctl_read!(mtd_get_info, MTD_IOC_MAGIC, MEMGETINFO_MODE, mtd_info_user);
+    ioctl_write_ptr!(mtd_erase, MTD_IOC_MAGIC, MEMERASE_MODE, erase_info_user);
+    ioctl_write_ptr!(mtd_get_bad_block, MTD_IOC_MAGIC, MEMGETBADBLOCK_MODE, i64);
+    ioctl_write_ptr!(ubi_vol_update, UBI_VOL_IOC_MAGIC, UBI_IOCVOLUP_MODE, i64);
 
-    pub fn is_nand(device: &Path) -> Result<bool, failure::Error> {
-        let device = std::fs::File::open(device)?;
+    pub fn get_info(device: &File) -> io::Result<MtdInfo> {
         let info = unsafe {
             let mut info = MaybeUninit::<mtd_info_user>::uninit();
-            mtd_get_info(device.as_raw_fd(), info.as_mut_ptr())?;
+            mtd_get_info(device.as_raw_fd(), info.as_mut_ptr()).map_err(to_io_error)?;
             info.assume_init()
         };
 
-        Ok(info.kind == MTD_NANDFLASH || info.kind == MTD_MLCNANDFLASH)
+        Ok(MtdInfo {
+            size: u64::from(info.size),
+            erase_size: u64::from(info.erasesize),
+            write_size: u64::from(info.writesize),
+            is_nand: info.kind == MTD_NANDFLASH || info.kind == MTD_MLCNANDFLASH,
+        })
+    }
+
+    pub fn erase(device: &File, offset: u64, length: u64) -> io::Result<()> {
+        let erase = erase_info_user { start: offset as u32, length: length as u32 };
+        unsafe { mtd_erase(device.as_raw_fd(), &erase) }.map_err(to_io_error)?;
+        Ok(())
+    }
+
+    pub fn is_bad_block(device: &File, offset: u64) -> io::Result<bool> {
+        let offset = offset as i64;
+        Ok(unsafe { mtd_get_bad_block(device.as_raw_fd(), &offset) }.map_err(to_io_error)? > 0)
+    }
+
+    pub fn start_volume_update(device: &File, size: u64) -> io::Result<()> {
+        let size = size as i64;
+        unsafe { ubi_vol_update(device.as_raw_fd(), &size) }.map_err(to_io_error)?;
+        Ok(())
+    }
+
+    fn to_io_error(err: nix::Error) -> io::Error {
+        io::Error::from(err.as_errno().unwrap_or(nix::errno::Errno::UnknownErrno))
     }
 }
 
@@ -179,6 +395,145 @@ pub(crate) mod tests {
         pub static ref SERIALIZE: Arc<Mutex<()>> = Arc::new(Mutex::default());
     }
 
+    // In memory flash, which as real ones can only clear bits when
+    // written, so data is only correct when written over erased blocks.
+    struct FakeFlash {
+        info: MtdInfo,
+        data: Vec<u8>,
+        bad_blocks: Vec<u64>,
+    }
+
+    impl FakeFlash {
+        fn new(info: MtdInfo, bad_blocks: Vec<u64>) -> Self {
+            FakeFlash { info, data: vec![0; info.size as usize], bad_blocks }
+        }
+
+        fn block(&self, n: u64) -> &[u8] {
+            let start = (n * self.info.erase_size) as usize;
+            &self.data[start..start + self.info.erase_size as usize]
+        }
+    }
+
+    impl Mtd for FakeFlash {
+        fn info(&self) -> MtdInfo {
+            self.info
+        }
+
+        fn is_bad_block(&self, offset: u64) -> io::Result<bool> {
+            Ok(self.bad_blocks.contains(&(offset / self.info.erase_size)))
+        }
+
+        fn erase_block(&mut self, offset: u64) -> io::Result<()> {
+            assert!(!self.is_bad_block(offset)?);
+            let start = offset as usize;
+            self.data[start..start + self.info.erase_size as usize]
+                .iter_mut()
+                .for_each(|b| *b = 0xFF);
+            Ok(())
+        }
+
+        fn write_at(&mut self, offset: u64, data: &[u8]) -> io::Result<()> {
+            assert!(!self.is_bad_block(offset)?);
+            assert_eq!(offset % self.info.write_size, 0);
+            assert_eq!(data.len() as u64 % self.info.write_size, 0);
+            self.data[offset as usize..].iter_mut().zip(data).for_each(|(b, d)| *b &= d);
+            Ok(())
+        }
+
+        fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> io::Result<()> {
+            buf.copy_from_slice(&self.data[offset as usize..offset as usize + buf.len()]);
+            Ok(())
+        }
+    }
+
+    fn image(size: usize) -> Vec<u8> {
+        (0..size).map(|i| (i % 251) as u8).collect()
+    }
+
+    #[test]
+    fn write_nor_image() {
+        let info = MtdInfo { size: 16 * 1024, erase_size: 4096, write_size: 1, is_nand: false };
+        let mut flash = FakeFlash::new(info, vec![]);
+        let image = image(10000);
+
+        write_image(&mut flash, &image[..]).unwrap();
+        assert_eq!(&flash.data[..image.len()], &image[..]);
+        assert!(flash.data[image.len()..].iter().all(|b| *b == 0xFF));
+    }
+
+    #[test]
+    fn write_nand_image_skipping_bad_blocks() {
+        let info = MtdInfo { size: 8 * 2048, erase_size: 2048, write_size: 512, is_nand: true };
+        let mut flash = FakeFlash::new(info, vec![1, 3]);
+        let image = image(5000);
+
+        write_image(&mut flash, &image[..]).unwrap();
+        assert_eq!(flash.block(0), &image[..2048]);
+        assert!(flash.block(1).iter().all(|b| *b == 0));
+        assert_eq!(flash.block(2), &image[2048..4096]);
+        assert!(flash.block(3).iter().all(|b| *b == 0));
+        assert_eq!(&flash.block(4)[..904], &image[4096..]);
+        assert!(flash.block(4)[904..].iter().all(|b| *b == 0xFF));
+    }
+
+    #[test]
+    fn verify_nand_image_skipping_bad_blocks() {
+        let info = MtdInfo { size: 8 * 2048, erase_size: 2048, write_size: 512, is_nand: true };
+        let mut flash = FakeFlash::new(info, vec![1, 3]);
+        let image = image(5000);
+
+        write_image(&mut flash, &image[..]).unwrap();
+        verify_image(&mut flash, &image[..]).unwrap();
+
+        flash.data[2 * 2048 + 10] ^= 0xFF;
+        assert!(verify_image(&mut flash, &image[..]).is_err());
+    }
+
+    #[test]
+    fn write_image_larger_than_device() {
+        let info = MtdInfo { size: 4 * 2048, erase_size: 2048, write_size: 512, is_nand: true };
+        let mut flash = FakeFlash::new(info, vec![2]);
+
+        assert!(write_image(&mut flash, &image(4 * 2048)[..]).is_err());
+    }
+
+    #[test]
+    fn ubi_volume_from_fake_sysfs() {
+        let sysfs = tempfile::tempdir().unwrap();
+        for (entry, name) in &[("ubi0_0", "rootfs"), ("ubi0_1", "data"), ("ubi1_0", "home")] {
+            fs::create_dir(sysfs.path().join(entry)).unwrap();
+            fs::write(sysfs.path().join(entry).join("name"), format!("{}\n", name)).unwrap();
+        }
+        fs::create_dir(sysfs.path().join("ubi0")).unwrap();
+
+        assert_eq!(
+            ubi_volume_from_sysfs(sysfs.path(), "data").unwrap(),
+            PathBuf::from("/dev/ubi0_1")
+        );
+        assert_eq!(
+            ubi_volume_from_sysfs(sysfs.path(), "home").unwrap(),
+            PathBuf::from("/dev/ubi1_0")
+        );
+        assert!(ubi_volume_from_sysfs(sysfs.path(), "dat").is_err());
+    }
+
+    #[test]
+    fn write_volume_with_exact_size() {
+        let data = image(5000);
+        let mut volume = Vec::new();
+
+        write_volume(&mut volume, data.len() as u64, &data[..]).unwrap();
+        assert_eq!(volume, data);
+    }
+
+    #[test]
+    fn write_volume_with_wrong_size() {
+        let data = image(5000);
+
+        assert!(write_volume(&mut Vec::new(), 6000, &data[..]).is_err());
+        assert!(write_volume(&mut Vec::new(), 4000, &data[..]).is_err());
+    }
+
     #[test]
     #[ignore]
     fn device_from_mtd_name() {
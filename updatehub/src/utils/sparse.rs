@@ -0,0 +1,283 @@
+// Copyright (C) 2019 O.S. Systems Sofware LTDA
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Android sparse images, as created by `img2simg`, which only carry the
+//! blocks of the image holding data. The format is described in
+//! https://android.googlesource.com/platform/system/core/+/master/libsparse/sparse_format.h
+
+use failure::{bail, ensure, format_err};
+use std::io::{self, BufRead, Read, Write};
+
+const MAGIC: u32 = 0xED26_FF3A;
+const FILE_HEADER_SIZE: usize = 28;
+const CHUNK_HEADER_SIZE: usize = 12;
+
+const CHUNK_TYPE_RAW: u16 = 0xCAC1;
+const CHUNK_TYPE_FILL: u16 = 0xCAC2;
+const CHUNK_TYPE_DONT_CARE: u16 = 0xCAC3;
+const CHUNK_TYPE_CRC32: u16 = 0xCAC4;
+
+/// Content of a region of the expanded image.
+pub(crate) enum Extent<'a> {
+    /// Data read from the sparse image
+    Data(&'a mut dyn Read),
+    /// Region filled with the repeated 4 bytes pattern
+    Fill([u8; 4]),
+}
+
+/// Returns the size of the expanded image when `input` starts with a
+/// sparse image header. Nothing is consumed from `input`.
+pub(crate) fn image_size<R: BufRead>(input: &mut R) -> io::Result<Option<u64>> {
+    let header = input.fill_buf()?;
+    if header.len() < FILE_HEADER_SIZE || le_u32(&header[0..4]) != MAGIC {
+        return Ok(None);
+    }
+
+    Ok(Some(u64::from(le_u32(&header[16..20])) * u64::from(le_u32(&header[12..16]))))
+}
+
+/// Walks the sparse image read from `input`, calling `f` for each region
+/// of the expanded image holding data. Regions are clipped to the
+/// window of `limit` bytes, or the whole image, starting at `skip`, and
+/// `f` gets their offset relative to `skip`. Returns the length of the
+/// expanded image within the window.
+pub(crate) fn walk<R, F>(
+    mut input: R,
+    skip: u64,
+    limit: Option<u64>,
+    mut f: F,
+) -> Result<u64, failure::Error>
+where
+    R: Read,
+    F: FnMut(u64, u64, Extent) -> Result<(), failure::Error>,
+{
+    let header = read_header(&mut input, FILE_HEADER_SIZE)?;
+    ensure!(le_u32(&header[0..4]) == MAGIC, "Invalid sparse image");
+    let file_header_size = usize::from(le_u16(&header[8..10]));
+    let chunk_header_size = usize::from(le_u16(&header[10..12]));
+    let block_size = u64::from(le_u32(&header[12..16]));
+    let chunks = le_u32(&header[20..24]);
+    ensure!(
+        file_header_size >= FILE_HEADER_SIZE && chunk_header_size >= CHUNK_HEADER_SIZE,
+        "Invalid sparse image header"
+    );
+    discard(&mut input, (file_header_size - FILE_HEADER_SIZE) as u64)?;
+
+    let end = limit.map(|limit| skip + limit).unwrap_or(u64::MAX);
+    let mut offset = 0;
+    for _ in 0..chunks {
+        let header = read_header(&mut input, chunk_header_size)?;
+        let len = u64::from(le_u32(&header[4..8])) * block_size;
+        let data_len = u64::from(le_u32(&header[8..12]))
+            .checked_sub(chunk_header_size as u64)
+            .ok_or_else(|| format_err!("Invalid sparse image chunk size"))?;
+
+        // Part of the chunk within the window
+        let clip = |pos: u64| pos.max(offset).min(offset + len);
+        let start = clip(skip);
+        let stop = clip(end).max(start);
+        match le_u16(&header[0..2]) {
+            CHUNK_TYPE_RAW => {
+                ensure!(data_len == len, "Invalid sparse image raw chunk");
+                discard(&mut input, start - offset)?;
+                if stop > start {
+                    let mut data = input.by_ref().take(stop - start);
+                    f(start - skip, stop - start, Extent::Data(&mut data))?;
+                    ensure!(data.limit() == 0, "Sparse image data was not fully consumed");
+                }
+                discard(&mut input, offset + len - stop)?;
+            }
+            CHUNK_TYPE_FILL => {
+                ensure!(data_len == 4, "Invalid sparse image fill chunk");
+                let mut pattern = [0; 4];
+                input.read_exact(&mut pattern)?;
+                if stop > start {
+                    f(start - skip, stop - start, Extent::Fill(pattern))?;
+                }
+            }
+            CHUNK_TYPE_DONT_CARE => {}
+            CHUNK_TYPE_CRC32 => discard(&mut input, data_len)?,
+            t => bail!("Unknown sparse image chunk type: {:#x}", t),
+        }
+        offset += len;
+    }
+
+    Ok(offset.min(end).saturating_sub(skip))
+}
+
+/// Writes `len` bytes of the repeated `pattern` into `output`.
+pub(crate) fn fill<W: Write>(pattern: [u8; 4], len: u64, output: &mut W) -> io::Result<()> {
+    io::copy(&mut fill_reader(pattern, len), output)?;
+
+    Ok(())
+}
+
+/// Returns a reader for `len` bytes of the repeated `pattern`.
+pub(crate) fn fill_reader(pattern: [u8; 4], len: u64) -> impl Read {
+    Pattern(pattern, 0).take(len)
+}
+
+struct Pattern([u8; 4], usize);
+
+impl Read for Pattern {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        for b in buf.iter_mut() {
+            *b = self.0[self.1 % 4];
+            self.1 += 1;
+        }
+
+        Ok(buf.len())
+    }
+}
+
+fn read_header<R: Read>(input: &mut R, size: usize) -> io::Result<Vec<u8>> {
+    let mut header = vec![0; size];
+    input.read_exact(&mut header)?;
+
+    Ok(header)
+}
+
+fn discard<R: Read>(input: &mut R, len: u64) -> io::Result<()> {
+    let discarded = io::copy(&mut input.take(len), &mut io::sink())?;
+    if discarded != len {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+
+    Ok(())
+}
+
+fn le_u16(bytes: &[u8]) -> u16 {
+    u16::from_le_bytes([bytes[0], bytes[1]])
+}
+
+fn le_u32(bytes: &[u8]) -> u32 {
+    u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    pub(crate) const BLOCK_SIZE: usize = 1024;
+
+    /// Chunks of the sparse image built by `sparse_image`.
+    pub(crate) enum Chunk {
+        Raw(Vec<u8>),
+        Fill([u8; 4], u32),
+        DontCare(u32),
+    }
+
+    /// Builds a sparse image with the `chunks`, returning it together
+    /// with its expanded content, where the holes are zeroed.
+    pub(crate) fn sparse_image(chunks: &[Chunk]) -> (Vec<u8>, Vec<u8>) {
+        let mut image = Vec::new();
+        let mut expanded = Vec::new();
+        let blocks = |len: usize| (len / BLOCK_SIZE) as u32;
+        let chunk_header = |image: &mut Vec<u8>, kind: u16, blocks: u32, data: usize| {
+            image.extend_from_slice(&kind.to_le_bytes());
+            image.extend_from_slice(&[0, 0]);
+            image.extend_from_slice(&blocks.to_le_bytes());
+            image.extend_from_slice(&((CHUNK_HEADER_SIZE + data) as u32).to_le_bytes());
+        };
+
+        for chunk in chunks {
+            match chunk {
+                Chunk::Raw(data) => {
+                    chunk_header(&mut image, CHUNK_TYPE_RAW, blocks(data.len()), data.len());
+                    image.extend_from_slice(data);
+                    expanded.extend_from_slice(data);
+                }
+                Chunk::Fill(pattern, n) => {
+                    chunk_header(&mut image, CHUNK_TYPE_FILL, *n, 4);
+                    image.extend_from_slice(pattern);
+                    fill(*pattern, u64::from(*n) * BLOCK_SIZE as u64, &mut expanded).unwrap();
+                }
+                Chunk::DontCare(n) => {
+                    chunk_header(&mut image, CHUNK_TYPE_DONT_CARE, *n, 0);
+                    expanded.resize(expanded.len() + *n as usize * BLOCK_SIZE, 0);
+                }
+            }
+        }
+
+        let mut header = Vec::new();
+        header.extend_from_slice(&MAGIC.to_le_bytes());
+        header.extend_from_slice(&[1, 0, 0, 0]);
+        header.extend_from_slice(&(FILE_HEADER_SIZE as u16).to_le_bytes());
+        header.extend_from_slice(&(CHUNK_HEADER_SIZE as u16).to_le_bytes());
+        header.extend_from_slice(&(BLOCK_SIZE as u32).to_le_bytes());
+        header.extend_from_slice(&blocks(expanded.len()).to_le_bytes());
+        header.extend_from_slice(&(chunks.len() as u32).to_le_bytes());
+        header.extend_from_slice(&[0; 4]);
+        header.extend_from_slice(&image);
+
+        (header, expanded)
+    }
+
+    // Expands the window of `image` into a zeroed buffer.
+    fn expand(image: &[u8], skip: u64, limit: Option<u64>) -> Vec<u8> {
+        let mut expanded = Vec::new();
+        let len = walk(image, skip, limit, |offset, len, extent| {
+            expanded.resize((offset + len) as usize, 0);
+            let region = &mut expanded[offset as usize..(offset + len) as usize];
+            match extent {
+                Extent::Data(data) => data.read_exact(region)?,
+                Extent::Fill(pattern) => fill(pattern, len, &mut &mut region[..])?,
+            }
+            Ok(())
+        })
+        .unwrap();
+        expanded.resize(len as usize, 0);
+
+        expanded
+    }
+
+    fn chunks() -> Vec<Chunk> {
+        vec![
+            Chunk::Raw((0..2 * BLOCK_SIZE).map(|i| (i % 251) as u8).collect()),
+            Chunk::DontCare(3),
+            Chunk::Fill([1, 2, 3, 4], 2),
+            Chunk::DontCare(1),
+        ]
+    }
+
+    #[test]
+    fn expanded_size() {
+        let (image, expanded) = sparse_image(&chunks());
+
+        assert_eq!(image_size(&mut &image[..]).unwrap(), Some(expanded.len() as u64));
+        assert_eq!(image_size(&mut &expanded[..]).unwrap(), None);
+    }
+
+    #[test]
+    fn walk_whole_image() {
+        let (image, expanded) = sparse_image(&chunks());
+
+        assert_eq!(expand(&image, 0, None), expanded);
+    }
+
+    #[test]
+    fn walk_window() {
+        let (image, expanded) = sparse_image(&chunks());
+
+        assert_eq!(expand(&image, 1000, Some(5000)), &expanded[1000..6000]);
+        assert_eq!(expand(&image, 6000, None), &expanded[6000..]);
+        assert_eq!(expand(&image, 0, Some(100_000)), expanded);
+    }
+
+    #[test]
+    fn walk_truncated_image() {
+        let (image, _) = sparse_image(&chunks());
+
+        assert!(walk(&image[..image.len() - 10], 0, None, |_, _, _| Ok(())).is_err());
+    }
+
+    #[test]
+    fn walk_invalid_chunk_size() {
+        let (mut image, _) = sparse_image(&chunks());
+        image[FILE_HEADER_SIZE + 8..FILE_HEADER_SIZE + 12].copy_from_slice(&4u32.to_le_bytes());
+
+        assert!(walk(&image[..], 0, None, |_, _, _| Ok(())).is_err());
+    }
+}
@@ -0,0 +1,160 @@
+// Copyright (C) 2019 O.S. Systems Sofware LTDA
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::utils::definitions::IdExt;
+use failure::{bail, ensure, format_err};
+use nix::libc;
+use pkg_schema::definitions::target_permissions::{Gid, Uid};
+use std::{ffi::CString, io, os::unix::ffi::OsStrExt, path::Path};
+
+// From https://github.com/torvalds/linux/blob/master/include/uapi/linux/posix_acl_xattr.h
+const ACL_EA_VERSION: u32 = 0x0002;
+const ACL_UNDEFINED_ID: u32 = u32::MAX;
+
+// From https://github.com/torvalds/linux/blob/master/include/uapi/linux/posix_acl.h
+const ACL_USER_OBJ: u16 = 0x01;
+const ACL_USER: u16 = 0x02;
+const ACL_GROUP_OBJ: u16 = 0x04;
+const ACL_GROUP: u16 = 0x08;
+const ACL_MASK: u16 = 0x10;
+const ACL_OTHER: u16 = 0x20;
+
+/// Sets the `name` extended attribute of `path`, without following
+/// symbolic links.
+pub(crate) fn set(path: &Path, name: &str, value: &[u8]) -> Result<(), failure::Error> {
+    let c_path = CString::new(path.as_os_str().as_bytes())?;
+    let c_name = CString::new(name)?;
+
+    let res = unsafe {
+        libc::lsetxattr(
+            c_path.as_ptr(),
+            c_name.as_ptr(),
+            value.as_ptr() as *const libc::c_void,
+            value.len(),
+            0,
+        )
+    };
+    if res != 0 {
+        bail!("Unable to set {} attribute of {:?}: {}", name, path, io::Error::last_os_error());
+    }
+
+    Ok(())
+}
+
+/// Encodes the `text` form of a POSIX ACL, as stored by tar archives,
+/// into the value of the `system.posix_acl_*` extended attributes.
+/// Named users and groups are looked up on the filesystem mounted at
+/// `root`, unless their numeric id is appended to the entry.
+pub(crate) fn posix_acl_from_text(text: &str, root: &Path) -> Result<Vec<u8>, failure::Error> {
+    let mut entries = Vec::new();
+
+    for entry in text.split([',', '\n']) {
+        let entry = entry.split('#').next().unwrap_or_default().trim();
+        if entry.is_empty() {
+            continue;
+        }
+
+        let fields = entry.split(':').collect::<Vec<_>>();
+        ensure!(fields.len() == 3 || fields.len() == 4, "Malformed ACL entry: {}", entry);
+
+        let qualifier = fields.get(3).unwrap_or(&fields[1]);
+        let tag = match (fields[0], qualifier.is_empty()) {
+            ("user", true) | ("u", true) => ACL_USER_OBJ,
+            ("user", false) | ("u", false) => ACL_USER,
+            ("group", true) | ("g", true) => ACL_GROUP_OBJ,
+            ("group", false) | ("g", false) => ACL_GROUP,
+            ("mask", _) | ("m", _) => ACL_MASK,
+            ("other", _) | ("o", _) => ACL_OTHER,
+            _ => bail!("Unknown ACL entry tag: {}", entry),
+        };
+        let id = match tag {
+            ACL_USER => match qualifier.parse() {
+                Ok(id) => id,
+                Err(_) => Uid::Name(qualifier.to_string()).resolve(root)?,
+            },
+            ACL_GROUP => match qualifier.parse() {
+                Ok(id) => id,
+                Err(_) => Gid::Name(qualifier.to_string()).resolve(root)?,
+            },
+            _ => ACL_UNDEFINED_ID,
+        };
+        let perm = fields[2].chars().try_fold(0u16, |perm, c| match c {
+            'r' => Ok(perm | 0x4),
+            'w' => Ok(perm | 0x2),
+            'x' => Ok(perm | 0x1),
+            '-' => Ok(perm),
+            _ => Err(format_err!("Invalid ACL permissions: {}", entry)),
+        })?;
+
+        entries.push((tag, id, perm));
+    }
+
+    // The kernel requires the entries ordered by tag and id
+    entries.sort();
+
+    let mut value = ACL_EA_VERSION.to_le_bytes().to_vec();
+    for (tag, id, perm) in entries {
+        value.extend_from_slice(&tag.to_le_bytes());
+        value.extend_from_slice(&perm.to_le_bytes());
+        value.extend_from_slice(&id.to_le_bytes());
+    }
+
+    Ok(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use std::fs;
+    use tempfile::tempdir;
+
+    fn acl_entry(tag: u16, perm: u16, id: u32) -> Vec<u8> {
+        [&tag.to_le_bytes()[..], &perm.to_le_bytes()[..], &id.to_le_bytes()[..]].concat()
+    }
+
+    #[test]
+    fn encode_posix_acl() {
+        let root = tempdir().unwrap();
+        fs::create_dir(root.path().join("etc")).unwrap();
+        fs::write(root.path().join("etc/passwd"), "operator:x:1001:1001::/:/bin/sh\n").unwrap();
+
+        let expected = [
+            ACL_EA_VERSION.to_le_bytes().to_vec(),
+            acl_entry(ACL_USER_OBJ, 0x6, ACL_UNDEFINED_ID),
+            acl_entry(ACL_USER, 0x4, 1000),
+            acl_entry(ACL_USER, 0x6, 1001),
+            acl_entry(ACL_GROUP_OBJ, 0x5, ACL_UNDEFINED_ID),
+            acl_entry(ACL_GROUP, 0x7, 20),
+            acl_entry(ACL_MASK, 0x7, ACL_UNDEFINED_ID),
+            acl_entry(ACL_OTHER, 0x0, ACL_UNDEFINED_ID),
+        ]
+        .concat();
+
+        assert_eq!(
+            posix_acl_from_text(
+                "user::rw-,user:operator:rw-,user:admin:r--:1000,group::r-x,group:20:rwx,\
+                 mask::rwx,other::---",
+                root.path()
+            )
+            .unwrap(),
+            expected
+        );
+    }
+
+    #[test]
+    fn invalid_posix_acl() {
+        assert!(posix_acl_from_text("user::rwz", Path::new("/")).is_err());
+        assert!(posix_acl_from_text("owner::rw-", Path::new("/")).is_err());
+        assert!(posix_acl_from_text("user:unknown:rw-", Path::new("/nonexistent")).is_err());
+    }
+
+    #[test]
+    fn set_attribute() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+
+        set(file.path(), "user.updatehub", b"value").unwrap();
+        assert!(set(Path::new("/nonexistent"), "user.updatehub", b"value").is_err());
+    }
+}